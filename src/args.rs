@@ -1,31 +1,213 @@
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::OnceLock;
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::sessions::SessionType;
 
 /// Custom greetd greeter for the Open Computing Facility
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
-    /// Enable demo mode, which mocks the greetd connection
-    #[arg(long)]
-    pub demo: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The config file to use instead of /etc/ocf-greeter/config.toml
+    #[arg(long, global = true, value_name = "PATH", env = "OCF_GREETER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Enable demo mode, which mocks the greetd connection. Optionally takes a
+    /// TOML scenario file describing the conversation to mock for each user
+    #[arg(long, value_name = "SCENARIO")]
+    pub demo: Option<Option<PathBuf>>,
+
+    /// Replay a conversation recorded with `--record` instead of mocking one
+    #[arg(long, value_name = "RECORDING", requires = "demo")]
+    pub replay: Option<PathBuf>,
+
+    /// Record every request to and response from greetd to a file, with
+    /// answers to secret prompts redacted
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    // Everything below can also be set in the config file, under the same name
+    // with underscores, or with an OCF_GREETER_* environment variable. Both
+    // take precedence over the config file. They're global, so that `doctor`
+    // and `sessions` can be given them after the subcommand as well.
+    /// The path of greetd's socket
+    #[arg(long, global = true, value_name = "PATH", env = "GREETD_SOCK")]
+    pub greetd_sock: Option<PathBuf>,
+
+    /// How many times to try connecting to greetd before giving up, backing
+    /// off exponentially in between [default: 5]
+    #[arg(long, global = true, value_name = "COUNT", env = "OCF_GREETER_CONNECT_ATTEMPTS")]
+    pub connect_attempts: Option<u32>,
+
+    /// How long to wait for greetd to answer a request, in seconds, or 0 to
+    /// wait as long as it takes [default: 60]
+    #[arg(long, global = true, value_name = "SECONDS", env = "OCF_GREETER_GREETD_TIMEOUT")]
+    pub greetd_timeout: Option<u64>,
+
+    /// The command X11 sessions are wrapped in to start an X server first.
+    /// `{exec}` is replaced by the session's command and `{display}` by a free
+    /// display number [default: "startx /usr/bin/env {exec} -- :{display}"]
+    #[arg(long, global = true, value_name = "COMMAND", env = "OCF_GREETER_X_LAUNCHER")]
+    pub x_launcher: Option<Argv>,
+
+    /// An extra directory to look for session files in, as TYPE:PATH where
+    /// TYPE is "wayland", "x11" or "tty". Can be given multiple times; earlier
+    /// directories take precedence over later ones, and all of them over the
+    /// XDG data directories. Also read from OCF_GREETER_SESSION_DIRS, as a
+    /// comma-separated list
+    // Not read by clap, which would split paths on the command line at commas
+    // too
+    #[arg(long = "session-dir", global = true, value_name = "TYPE:PATH")]
+    pub session_dirs: Vec<SessionDir>,
 
     /// The default session, e.g. "plasma"
-    #[arg(long)]
+    #[arg(long, global = true, value_name = "SLUG", env = "OCF_GREETER_DEFAULT_SESSION")]
     pub default_session: Option<String>,
 
+    /// The icon theme to look up session icons in, before the fallback
+    /// "hicolor" theme
+    #[arg(long, global = true, value_name = "NAME", env = "OCF_GREETER_ICON_THEME")]
+    pub icon_theme: Option<String>,
+
+    /// Where to keep state between logins, such as the session each user last
+    /// started [default: /var/cache/ocf-greeter]
+    #[arg(long, global = true, value_name = "PATH", env = "OCF_GREETER_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
     /// The background image to display, if any
-    #[arg(long)]
+    #[arg(long, global = true, value_name = "PATH", env = "OCF_GREETER_BACKGROUND")]
     pub background: Option<PathBuf>,
 
     /// The logo image to display, if any
-    #[arg(long)]
+    #[arg(long, global = true, value_name = "PATH", env = "OCF_GREETER_LOGO")]
     pub logo: Option<PathBuf>,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a fake greetd on a Unix socket for testing, playing back a demo scenario
+    FakeGreetd {
+        /// The path of the socket to listen on
+        socket: PathBuf,
+
+        /// The scenario to play back, in the same format as `--demo`
+        #[arg(long)]
+        scenario: Option<PathBuf>,
+    },
+
+    /// List the sessions that would be offered
+    Sessions {
+        /// List every session file found instead, including where it came
+        /// from and why it was shadowed or rejected, if it was
+        #[arg(long, short)]
+        verbose: bool,
+    },
+
+    /// Check the config, images, sessions and greetd connection, and report
+    /// any problems. Exits with a non-zero status if there are any
+    Doctor,
+}
+
+/// A command line given as a single shell-quoted string.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Argv(pub Vec<String>);
+
+impl FromStr for Argv {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match shlex::split(s) {
+            Some(argv) if !argv.is_empty() => Ok(Self(argv)),
+            Some(_) => Err("command is empty".to_owned()),
+            None => Err("command has unbalanced quotes".to_owned()),
+        }
+    }
+}
+
+impl TryFrom<String> for Argv {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A directory of session files, all of the same type.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionDir {
+    pub r#type: SessionType,
+    pub path: PathBuf,
+}
+
+impl FromStr for SessionDir {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (r#type, path) = s.split_once(':').ok_or("expected TYPE:PATH")?;
+        Ok(Self { r#type: r#type.parse()?, path: PathBuf::from(path) })
+    }
+}
+
+static SESSION_DIRS_ENV: &str = "OCF_GREETER_SESSION_DIRS";
+
+impl Args {
+    /// Fills in the settings that clap can't read from the environment itself,
+    /// unless they were given on the command line.
+    pub fn read_env(&mut self) -> Result<(), clap::Error> {
+        if self.session_dirs.is_empty() {
+            if let Ok(value) = std::env::var(SESSION_DIRS_ENV) {
+                self.session_dirs = value
+                    .split(',')
+                    .filter(|dir| !dir.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| {
+                        let message = format!("invalid value in {}: {}", SESSION_DIRS_ENV, error);
+                        Self::command().error(ErrorKind::InvalidValue, message)
+                    })?;
+            }
+        }
+        Ok(())
+    }
+}
+
 static ARGS: OnceLock<Args> = OnceLock::new();
 
 pub fn get_args() -> &'static Args {
-    ARGS.get_or_init(Args::parse)
+    ARGS.get_or_init(|| {
+        let mut args = Args::parse();
+        args.read_env().unwrap_or_else(|error| error.exit());
+        args
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_settings_after_subcommand() {
+        let args = Args::try_parse_from([
+            "ocf-greeter",
+            "doctor",
+            "--greetd-sock",
+            "/run/greetd.sock",
+            "--session-dir",
+            "wayland:/etc/ocf-greeter/sessions",
+        ])
+        .unwrap();
+
+        assert!(matches!(args.command, Some(Command::Doctor)));
+        assert_eq!(args.greetd_sock, Some(PathBuf::from("/run/greetd.sock")));
+        assert_eq!(args.session_dirs[0].path, PathBuf::from("/etc/ocf-greeter/sessions"));
+    }
 }
@@ -2,6 +2,8 @@ use dioxus::prelude::*;
 
 #[derive(PartialEq, Props, Clone)]
 pub struct ButtonProps {
+    #[props(default = false)]
+    disabled: bool,
     children: Element,
 }
 
@@ -9,7 +11,30 @@ pub struct ButtonProps {
 pub fn Button(props: ButtonProps) -> Element {
     rsx! {
         button {
-            class: "rounded bg-sky-900 px-3 py-2 font-semibold text-white hover:bg-sky-800 focus-visible:outline-2 focus-visible:outline-offset-2 focus-visible:outline-sky-900",
+            class: "rounded bg-sky-900 px-3 py-2 font-semibold text-white hover:bg-sky-800 focus-visible:outline-2 focus-visible:outline-offset-2 focus-visible:outline-sky-900 disabled:opacity-50",
+            disabled: props.disabled,
+            {props.children}
+        }
+    }
+}
+
+#[derive(PartialEq, Props, Clone)]
+pub struct SecondaryButtonProps {
+    #[props(default = false)]
+    disabled: bool,
+    onclick: EventHandler<MouseEvent>,
+    children: Element,
+}
+
+/// A less prominent button that doesn't submit the surrounding form.
+#[component]
+pub fn SecondaryButton(props: SecondaryButtonProps) -> Element {
+    rsx! {
+        button {
+            class: "rounded bg-black/10 px-3 py-2 text-sm focus:bg-black/20 disabled:opacity-50",
+            type: "button",
+            disabled: props.disabled,
+            onclick: props.onclick,
             {props.children}
         }
     }
@@ -1,7 +1,7 @@
 mod button;
 mod input;
-mod session_selector;
+mod session_picker;
 
-pub use button::Button;
+pub use button::{Button, SecondaryButton};
 pub use input::Input;
-pub use session_selector::SessionSelector;
+pub use session_picker::SessionPicker;
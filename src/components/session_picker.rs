@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use dioxus::prelude::*;
+
+use crate::sessions::{Session, SessionType};
+
+#[derive(PartialEq, Props, Clone)]
+pub struct SessionPickerProps {
+    sessions: Vec<Session>,
+    session: Option<Session>,
+    onchange: EventHandler<Session>,
+}
+
+#[derive(PartialEq, Props, Clone)]
+struct SessionIconProps {
+    icon: Option<PathBuf>,
+}
+
+/// A session's icon, or an empty space of the same size if it has none.
+#[component]
+fn SessionIcon(props: SessionIconProps) -> Element {
+    match props.icon.as_deref().and_then(|icon| icon.to_str()) {
+        Some(src) => rsx! { img { src: src, alt: "", class: "h-6 w-6 shrink-0" } },
+        None => rsx! { div { class: "h-6 w-6 shrink-0" } },
+    }
+}
+
+fn option_id(session: &Session) -> String {
+    format!("session-{}", session.slug)
+}
+
+/// A button showing the selected session, which opens a list of all sessions
+/// grouped by type. The list can be navigated with the arrow keys, and closed
+/// with Escape without cancelling the rest of the form.
+#[component]
+pub fn SessionPicker(props: SessionPickerProps) -> Element {
+    let mut open = use_signal(|| false);
+    // The index into `sessions` of the option the keyboard is on
+    let mut highlighted = use_signal(|| 0);
+    let mut toggle_element: Signal<Option<Rc<MountedData>>> = use_signal(|| None);
+
+    let groups: Vec<(SessionType, Vec<Session>)> = SessionType::ALL
+        .into_iter()
+        .map(|r#type| {
+            let sessions = props.sessions.iter().filter(|session| session.r#type == r#type);
+            (r#type, sessions.cloned().collect::<Vec<_>>())
+        })
+        .filter(|(_, sessions)| !sessions.is_empty())
+        .collect();
+    // The sessions in the order they're listed in
+    let sessions: Vec<Session> =
+        groups.iter().flat_map(|(_, sessions)| sessions).cloned().collect();
+
+    let selected = props
+        .session
+        .as_ref()
+        .and_then(|selected| sessions.iter().position(|session| session.slug == selected.slug));
+
+    let mut close = move || {
+        open.set(false);
+        if let Some(toggle) = toggle_element() {
+            spawn(async move {
+                _ = toggle.set_focus(true).await;
+            });
+        }
+    };
+
+    let mut show = move || {
+        highlighted.set(selected.unwrap_or(0));
+        open.set(true);
+    };
+
+    let onkeydown_list = {
+        let sessions = sessions.clone();
+        move |event: KeyboardEvent| {
+            let last = sessions.len().saturating_sub(1);
+            let mut select = || {
+                if let Some(session) = sessions.get(highlighted()) {
+                    props.onchange.call(session.clone());
+                }
+                close();
+            };
+            match event.key() {
+                Key::ArrowDown => highlighted.set((highlighted() + 1).min(last)),
+                Key::ArrowUp => highlighted.set(highlighted().saturating_sub(1)),
+                Key::Home => highlighted.set(0),
+                Key::End => highlighted.set(last),
+                Key::Enter => select(),
+                Key::Character(character) if character == " " => select(),
+                Key::Escape => {
+                    // Only close the list, rather than going back in the form
+                    event.stop_propagation();
+                    close();
+                }
+                _ => return,
+            }
+            // Keep the arrow keys from scrolling the page
+            event.prevent_default();
+        }
+    };
+
+    let toggle_label = match &props.session {
+        Some(session) => rsx! {
+            SessionIcon { icon: session.icon.clone() }
+            span { {session.name.as_str()} }
+        },
+        None => rsx! { span { "Select a session" } },
+    };
+
+    let options = groups.iter().map(|(r#type, group)| {
+        let options = group.iter().map(|session| {
+            let index = sessions.iter().position(|s| s.slug == session.slug).unwrap();
+            let onclick = {
+                let session = session.clone();
+                move |_| {
+                    props.onchange.call(session.clone());
+                    close();
+                }
+            };
+
+            rsx! {
+                li {
+                    key: "{session.slug}",
+                    id: option_id(session),
+                    role: "option",
+                    aria_selected: selected == Some(index),
+                    class: if highlighted() == index { "flex items-start gap-2 rounded px-2 py-1 bg-black/10" } else { "flex items-start gap-2 rounded px-2 py-1" },
+                    onmouseenter: move |_| highlighted.set(index),
+                    onclick: onclick,
+                    SessionIcon { icon: session.icon.clone() }
+                    div {
+                        class: "flex flex-col",
+                        div {
+                            class: "flex items-center gap-2",
+                            span { class: "font-medium", {session.name.as_str()} }
+                            span { class: "rounded bg-black/10 px-1", {r#type.label()} }
+                        }
+                        if let Some(comment) = &session.comment {
+                            span { class: "opacity-75", {comment.as_str()} }
+                        }
+                    }
+                }
+            }
+        });
+
+        rsx! {
+            li {
+                key: "{r#type}",
+                role: "group",
+                aria_label: r#type.label(),
+                div { class: "px-2 pt-2 pb-1 font-semibold opacity-50", {r#type.label()} }
+                ul { role: "presentation", {options} }
+            }
+        }
+    });
+
+    rsx! {
+        div {
+            class: "relative self-end text-xs",
+            button {
+                class: "flex items-center gap-2 rounded bg-black/10 px-2 py-1 focus:bg-black/20",
+                type: "button",
+                aria_haspopup: "listbox",
+                aria_expanded: open(),
+                title: props.session.as_ref().and_then(|session| session.comment.clone()),
+                onmounted: move |element: MountedEvent| toggle_element.set(Some(element.data())),
+                // Otherwise clicking the button to close the list would take focus away from the
+                // list, closing it, and then the click would open it again
+                onmousedown: move |event: MouseEvent| event.prevent_default(),
+                onclick: move |_| if open() { open.set(false) } else { show() },
+                onkeydown: move |event: KeyboardEvent| {
+                    if matches!(event.key(), Key::ArrowDown | Key::ArrowUp) {
+                        event.prevent_default();
+                        show();
+                    }
+                },
+                {toggle_label}
+            }
+            if open() {
+                ul {
+                    class: "absolute bottom-full right-0 mb-2 w-72 max-h-80 overflow-y-auto rounded-lg bg-white/30 p-1 shadow backdrop-blur-xl focus:outline-none",
+                    role: "listbox",
+                    tabindex: 0,
+                    aria_activedescendant: sessions.get(highlighted()).map(option_id),
+                    onmounted: move |element: MountedEvent| async move {
+                        _ = element.set_focus(true).await;
+                    },
+                    onkeydown: onkeydown_list,
+                    onfocusout: move |_| open.set(false),
+                    {options}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! The greeter's config file. Settings that can also be given on the command
+//! line or in `OCF_GREETER_*` environment variables use the same names, and
+//! are overridden by them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::args::{get_args, Args, Argv, SessionDir};
+use crate::icons::find_icon;
+use crate::sessions::{Session, SessionType};
+use crate::wrapper::Wrapper;
+
+static DEFAULT_CONFIG_PATH: &str = "/etc/ocf-greeter/config.toml";
+
+fn default_connect_attempts() -> u32 {
+    5
+}
+
+fn default_greetd_timeout() -> u64 {
+    60
+}
+
+fn default_x_launcher() -> Argv {
+    "startx /usr/bin/env {exec} -- :{display}".parse().unwrap()
+}
+
+fn default_state_dir() -> PathBuf {
+    PathBuf::from("/var/cache/ocf-greeter")
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Branding
+    pub background: Option<PathBuf>,
+    pub logo: Option<PathBuf>,
+
+    // Transport
+    pub greetd_sock: Option<PathBuf>,
+    #[serde(default = "default_connect_attempts")]
+    pub connect_attempts: u32,
+    #[serde(default = "default_greetd_timeout")]
+    pub greetd_timeout: u64,
+
+    // Sessions
+    pub default_session: Option<String>,
+    #[serde(default)]
+    pub session_dirs: Vec<SessionDir>,
+    pub icon_theme: Option<String>,
+    #[serde(default = "default_x_launcher")]
+    pub x_launcher: Argv,
+    #[serde(default = "default_state_dir")]
+    pub state_dir: PathBuf,
+
+    /// Sessions defined directly in the config rather than by a session file.
+    /// These take precedence over session files with the same slug.
+    #[serde(default)]
+    pub sessions: Vec<CustomSession>,
+
+    /// Environment variables set in every session.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// The keyboard layout sessions are started with, if not the one the
+    /// greeter itself was started with. Short for `XKB_DEFAULT_LAYOUT` in
+    /// `env`, which it takes precedence over.
+    pub keyboard_layout: Option<String>,
+
+    /// The wrapper every session is started in, unless overridden in
+    /// `wrappers`.
+    #[serde(default)]
+    pub wrapper: Wrapper,
+
+    /// Wrappers for particular sessions, by slug.
+    #[serde(default)]
+    pub wrappers: HashMap<String, Wrapper>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomSession {
+    pub slug: String,
+    pub name: String,
+    pub comment: Option<String>,
+    /// An icon name or absolute path, like `Icon=` in a session file.
+    pub icon: Option<String>,
+    pub exec: Vec<String>,
+    pub r#type: SessionType,
+    #[serde(default)]
+    pub desktop_names: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CustomSession {
+    /// Looks up the session's icon in `icon_theme`, as for session files.
+    pub fn to_session(&self, icon_theme: Option<&str>) -> Session {
+        Session {
+            slug: self.slug.clone(),
+            name: self.name.clone(),
+            comment: self.comment.clone(),
+            exec: self.exec.clone(),
+            r#type: self.r#type,
+            desktop_names: self.desktop_names.clone(),
+            icon: self.icon.as_deref().and_then(|icon| find_icon(icon, icon_theme)),
+            env: self.env.iter().map(|(key, value)| (key.clone(), value.clone())).collect(),
+            hidden: false,
+            try_exec: None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        toml::from_str("").expect("empty config should be valid")
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("failed to parse config {}", path.display()))
+    }
+
+    /// Overrides settings with those given on the command line or in the
+    /// environment.
+    pub fn apply_args(&mut self, args: &Args) {
+        fn apply<T: Clone>(setting: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *setting = value.clone();
+            }
+        }
+        fn apply_optional<T: Clone>(setting: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                setting.clone_from(value);
+            }
+        }
+
+        apply_optional(&mut self.background, &args.background);
+        apply_optional(&mut self.logo, &args.logo);
+        apply_optional(&mut self.greetd_sock, &args.greetd_sock);
+        apply(&mut self.connect_attempts, &args.connect_attempts);
+        apply(&mut self.greetd_timeout, &args.greetd_timeout);
+        apply_optional(&mut self.default_session, &args.default_session);
+        if !args.session_dirs.is_empty() {
+            self.session_dirs.clone_from(&args.session_dirs);
+        }
+        apply_optional(&mut self.icon_theme, &args.icon_theme);
+        apply(&mut self.x_launcher, &args.x_launcher);
+        apply(&mut self.state_dir, &args.state_dir);
+    }
+
+    /// The environment every session is started with, i.e. `env` and
+    /// `keyboard_layout`.
+    pub fn site_environment(&self) -> BTreeMap<String, String> {
+        let mut environment = self.env.clone();
+        if let Some(keyboard_layout) = &self.keyboard_layout {
+            environment.insert("XKB_DEFAULT_LAYOUT".to_owned(), keyboard_layout.clone());
+        }
+        environment
+    }
+
+    pub fn wrapper_for(&self, slug: &str) -> &Wrapper {
+        self.wrappers.get(slug).unwrap_or(&self.wrapper)
+    }
+
+    /// The config file given by `--config`, or the default one if it exists.
+    pub fn path() -> Option<&'static Path> {
+        let path = Self::search_path();
+        (get_args().config.is_some() || path.exists()).then_some(path)
+    }
+
+    /// The config file given by `--config`, or the default one, whether or not
+    /// it exists.
+    pub fn search_path() -> &'static Path {
+        get_args().config.as_deref().unwrap_or(Path::new(DEFAULT_CONFIG_PATH))
+    }
+}
+
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// Loads the config file and applies the command line on top of it.
+pub fn load() -> Result<Config> {
+    let mut config = match Config::path() {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    config.apply_args(get_args());
+    Ok(config)
+}
+
+/// Sets the config returned by [`get_config`]. Must be called before it.
+pub fn init(config: Config) {
+    let mut current = CONFIG.write().unwrap();
+    assert!(current.is_none(), "config should only be loaded once");
+    *current = Some(Arc::new(config));
+}
+
+/// Loads the config file again, replacing the current config if it's valid.
+pub fn reload() -> Result<()> {
+    let config = load()?;
+    *CONFIG.write().unwrap() = Some(Arc::new(config));
+    Ok(())
+}
+
+/// The current config. It may be replaced at any time by [`reload`], so hold
+/// on to it rather than calling this repeatedly for settings that have to
+/// agree with each other.
+pub fn get_config() -> Arc<Config> {
+    CONFIG.read().unwrap().clone().expect("config should be loaded at startup")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use clap::Parser;
+
+    use super::*;
+
+    /// Held by tests that set environment variables, which the others would
+    /// otherwise see.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    static SITE_CONFIG: &str = r#"
+        connect_attempts = 3
+        default_session = "gnome"
+        session_dirs = [{ type = "wayland", path = "/etc/ocf-greeter/sessions" }]
+    "#;
+
+    /// Loads `config` and applies `args` and `env` on top of it, the way
+    /// [`load`] does.
+    fn layered(config: &str, args: &[&str], env: &[(&str, &str)]) -> Config {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+
+        for (key, value) in env {
+            std::env::set_var(key, value);
+        }
+        let result =
+            Args::try_parse_from(["ocf-greeter"].iter().chain(args)).and_then(|mut args| {
+                args.read_env()?;
+                Ok(args)
+            });
+        for (key, _) in env {
+            std::env::remove_var(key);
+        }
+
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.apply_args(&result.unwrap());
+        config
+    }
+
+    fn session_dir(r#type: SessionType, path: &str) -> SessionDir {
+        SessionDir { r#type, path: PathBuf::from(path) }
+    }
+
+    #[test]
+    fn uses_defaults_without_config() {
+        let config = layered("", &[], &[]);
+
+        assert_eq!(config.connect_attempts, 5);
+        assert_eq!(config.default_session, None);
+        assert_eq!(config.x_launcher, default_x_launcher());
+        assert_eq!(config.state_dir, default_state_dir());
+    }
+
+    #[test]
+    fn uses_config_file_without_overrides() {
+        let config = layered(SITE_CONFIG, &[], &[]);
+
+        assert_eq!(config.connect_attempts, 3);
+        assert_eq!(config.default_session.as_deref(), Some("gnome"));
+        assert_eq!(
+            config.session_dirs,
+            [session_dir(SessionType::Wayland, "/etc/ocf-greeter/sessions")]
+        );
+    }
+
+    #[test]
+    fn environment_overrides_config_file() {
+        let config = layered(
+            SITE_CONFIG,
+            &[],
+            &[
+                ("OCF_GREETER_CONNECT_ATTEMPTS", "7"),
+                ("OCF_GREETER_SESSION_DIRS", "x11:/opt/x,wayland:/opt/wayland"),
+            ],
+        );
+
+        assert_eq!(config.connect_attempts, 7);
+        assert_eq!(
+            config.session_dirs,
+            [
+                session_dir(SessionType::X11, "/opt/x"),
+                session_dir(SessionType::Wayland, "/opt/wayland")
+            ]
+        );
+        // Settings that aren't overridden are kept
+        assert_eq!(config.default_session.as_deref(), Some("gnome"));
+    }
+
+    #[test]
+    fn command_line_overrides_environment() {
+        let config = layered(
+            SITE_CONFIG,
+            &["--connect-attempts", "9", "--session-dir", "tty:/opt/a,b"],
+            &[("OCF_GREETER_CONNECT_ATTEMPTS", "7"), ("OCF_GREETER_SESSION_DIRS", "x11:/opt/x")],
+        );
+
+        assert_eq!(config.connect_attempts, 9);
+        // Commas are only list separators in the environment variable
+        assert_eq!(config.session_dirs, [session_dir(SessionType::Tty, "/opt/a,b")]);
+        assert_eq!(config.default_session.as_deref(), Some("gnome"));
+    }
+
+    #[test]
+    fn rejects_invalid_session_dirs_in_environment() {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|error| error.into_inner());
+        std::env::set_var("OCF_GREETER_SESSION_DIRS", "x11:/opt/x,/opt/y");
+        let mut args = Args::try_parse_from(["ocf-greeter"]).unwrap();
+        let result = args.read_env();
+        std::env::remove_var("OCF_GREETER_SESSION_DIRS");
+
+        assert!(result.unwrap_err().to_string().contains("OCF_GREETER_SESSION_DIRS"));
+    }
+
+    #[test]
+    fn parses_custom_sessions() {
+        let config: Config = toml::from_str(
+            r#"
+            [[sessions]]
+            slug = "console"
+            name = "Text console (bash)"
+            exec = ["bash", "--login"]
+            type = "tty"
+            env = { TERM = "linux" }
+            "#,
+        )
+        .unwrap();
+
+        let session = config.sessions[0].to_session(None);
+        assert_eq!(session.slug, "console");
+        assert_eq!(session.name, "Text console (bash)");
+        assert_eq!(session.exec, ["bash", "--login"]);
+        assert_eq!(session.r#type, SessionType::Tty);
+        assert_eq!(session.env, [("TERM".to_owned(), "linux".to_owned())]);
+        assert!(!session.hidden);
+    }
+
+    #[test]
+    fn sets_keyboard_layout_in_site_environment() {
+        let config: Config = toml::from_str(
+            r#"
+            keyboard_layout = "us"
+            env = { EDITOR = "nano", XKB_DEFAULT_LAYOUT = "de" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.site_environment().into_iter().collect::<Vec<_>>(),
+            [
+                ("EDITOR".to_owned(), "nano".to_owned()),
+                ("XKB_DEFAULT_LAYOUT".to_owned(), "us".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,499 @@
+//! A parser for desktop entry files, following the [Desktop Entry
+//! Specification](https://specifications.freedesktop.org/desktop-entry-spec/latest/).
+//!
+//! Values are kept raw until they are asked for with a particular type, since
+//! strings, lists and `Exec=` each have their own escaping rules.
+
+use std::path::Path;
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopEntry {
+    groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    /// Key-value pairs in file order. Localized keys such as `Name[de]` are
+    /// kept as-is.
+    entries: Vec<(String, String)>,
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let (name, locale) = match key.split_once('[') {
+        Some((name, locale)) => match locale.strip_suffix(']') {
+            Some(locale) => (name, Some(locale)),
+            None => return false,
+        },
+        None => (key, None),
+    };
+
+    let valid_name =
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let valid_locale = locale.is_none_or(|locale| {
+        !locale.is_empty()
+            && locale.chars().all(|c| c.is_ascii_alphanumeric() || "_@.-".contains(c))
+    });
+
+    valid_name && valid_locale
+}
+
+/// A locale as used to pick localized keys like `Name[de_DE]`, i.e.
+/// `lang_COUNTRY.ENCODING@MODIFIER` with everything but `lang` optional. The
+/// encoding is irrelevant for matching, so it isn't kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    pub lang: String,
+    pub country: Option<String>,
+    pub modifier: Option<String>,
+}
+
+impl Locale {
+    /// Parses a POSIX locale name. Returns `None` for the `C` and `POSIX`
+    /// locales, which have no translations.
+    pub fn parse(name: &str) -> Option<Self> {
+        let (rest, modifier) = match name.split_once('@') {
+            Some((rest, modifier)) => (rest, Some(modifier.to_owned())),
+            None => (name, None),
+        };
+        let rest = rest.split_once('.').map_or(rest, |(rest, _encoding)| rest);
+        let (lang, country) = match rest.split_once('_') {
+            Some((lang, country)) => (lang, Some(country.to_owned())),
+            None => (rest, None),
+        };
+
+        if lang.is_empty() || lang == "C" || lang == "POSIX" {
+            return None;
+        }
+        Some(Self { lang: lang.to_owned(), country, modifier })
+    }
+
+    /// The locale messages should be shown in, following the usual precedence
+    /// of `LC_ALL`, then `LC_MESSAGES`, then `LANG`.
+    pub fn from_env() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Self::parse(&value))
+    }
+
+    /// The locale suffixes to look for, from best to worst match, as given by
+    /// the spec.
+    fn candidates(&self) -> Vec<String> {
+        let Self { lang, country, modifier } = self;
+        let mut candidates = Vec::new();
+
+        if let (Some(country), Some(modifier)) = (country, modifier) {
+            candidates.push(format!("{}_{}@{}", lang, country, modifier));
+        }
+        if let Some(country) = country {
+            candidates.push(format!("{}_{}", lang, country));
+        }
+        if let Some(modifier) = modifier {
+            candidates.push(format!("{}@{}", lang, modifier));
+        }
+        candidates.push(lang.clone());
+
+        candidates
+    }
+}
+
+impl DesktopEntry {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut groups: Vec<Group> = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                if groups.iter().any(|group| group.name == name) {
+                    bail!("line {}: duplicate group [{}]", line_number, name);
+                }
+                groups.push(Group { name: name.to_owned(), entries: Vec::new() });
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                eyre!("line {}: expected a group header or Key=Value", line_number)
+            })?;
+            let (key, value) = (key.trim_end(), value.trim_start());
+
+            if !is_valid_key(key) {
+                bail!("line {}: invalid key {:?}", line_number, key);
+            }
+
+            let group = groups
+                .last_mut()
+                .ok_or_else(|| eyre!("line {}: {}= appears before any group", line_number, key))?;
+            if group.get(key).is_some() {
+                bail!("line {}: duplicate key {}= in [{}]", line_number, key, group.name);
+            }
+            group.entries.push((key.to_owned(), value.to_owned()));
+        }
+
+        Ok(Self { groups })
+    }
+
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+}
+
+impl Group {
+    /// The raw value of a key, without any unescaping.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    /// A value of type string, with `\s`, `\n`, `\t`, `\r` and `\\` unescaped.
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.get(key).map(|value| unescape(value, false).remove(0))
+    }
+
+    /// A value of type localestring, taken from the key for the best matching
+    /// locale, falling back to the unlocalized key.
+    pub fn localized_string(&self, key: &str, locale: Option<&Locale>) -> Option<String> {
+        let localized = locale.into_iter().flat_map(Locale::candidates).find_map(|candidate| {
+            self.get(&format!("{}[{}]", key, candidate))
+                .map(|value| unescape(value, false).remove(0))
+        });
+        localized.or_else(|| self.string(key))
+    }
+
+    /// A value of type string(s), split on unescaped semicolons. Empty
+    /// elements, such as the one after the usual trailing semicolon, are
+    /// dropped.
+    pub fn strings(&self, key: &str) -> Option<Vec<String>> {
+        self.get(key).map(|value| {
+            unescape(value, true).into_iter().filter(|element| !element.is_empty()).collect()
+        })
+    }
+
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => bail!("invalid boolean {:?} for {}=", value, key),
+        }
+    }
+
+    /// The value of `Exec=`, split into arguments with field codes removed.
+    pub fn exec(&self) -> Result<Option<Vec<String>>> {
+        self.string("Exec").map(|exec| split_exec(&exec)).transpose()
+    }
+}
+
+/// Unescapes a string value. If `list` is set, the value is also split on
+/// unescaped semicolons, and `\;` is unescaped to a semicolon.
+fn unescape(value: &str, list: bool) -> Vec<String> {
+    let mut elements = vec![String::new()];
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        let element = elements.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some('s') => element.push(' '),
+                Some('n') => element.push('\n'),
+                Some('t') => element.push('\t'),
+                Some('r') => element.push('\r'),
+                Some('\\') => element.push('\\'),
+                Some(';') if list => element.push(';'),
+                // Not an escape sequence we know, so leave it alone
+                Some(c) => element.extend(['\\', c]),
+                None => element.push('\\'),
+            },
+            ';' if list => elements.push(String::new()),
+            c => element.push(c),
+        }
+    }
+
+    elements
+}
+
+/// Splits an (already unescaped) `Exec=` value into arguments, following the
+/// spec's quoting rules. Field codes such as `%f` and `%U` are removed, since
+/// sessions are never started with files or URLs.
+pub fn split_exec(exec: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut chars = exec.chars().peekable();
+
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        let Some(first) = chars.next() else {
+            break;
+        };
+
+        if first == '"' {
+            let mut arg = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next_if(|c| "\"`$\\".contains(*c)) {
+                        Some(c) => arg.push(c),
+                        None => arg.push('\\'),
+                    },
+                    Some(c) => arg.push(c),
+                    None => bail!("unterminated quote in Exec="),
+                }
+            }
+            args.push(arg);
+            continue;
+        }
+
+        let mut arg = String::new();
+        let mut had_field_code = false;
+        let mut next = Some(first);
+
+        while let Some(c) = next.filter(|c| *c != ' ') {
+            if c == '%' {
+                match chars.next() {
+                    Some('%') => arg.push('%'),
+                    Some(
+                        'f' | 'F' | 'u' | 'U' | 'd' | 'D' | 'n' | 'N' | 'i' | 'c' | 'k' | 'v' | 'm',
+                    ) => had_field_code = true,
+                    Some(code) => bail!("unknown field code %{} in Exec=", code),
+                    None => bail!("incomplete field code in Exec="),
+                }
+            } else {
+                arg.push(c);
+            }
+            next = chars.next();
+        }
+
+        // Arguments that were nothing but a field code disappear entirely
+        if !(arg.is_empty() && had_field_code) {
+            args.push(arg);
+        }
+    }
+
+    if args.is_empty() {
+        bail!("Exec= is empty");
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PLASMA: &str = r#"[Desktop Entry]
+Exec=/usr/lib/plasma-dbus-run-session-if-needed /usr/bin/startplasma-wayland
+TryExec=/usr/bin/startplasma-wayland
+DesktopNames=KDE
+Name=Plasma (Wayland)
+Name[de]=Plasma (Wayland)
+Name[zh_CN]=Plasma (Wayland)
+Comment=Plasma by KDE
+Comment[fr]=Plasma par KDE
+X-KDE-PluginInfo-Version=6.2.4
+"#;
+
+    static GNOME_CLASSIC: &str = r#"[Desktop Entry]
+Name=GNOME Classic
+Comment=This session logs you into GNOME Classic
+Exec=env GNOME_SHELL_SESSION_MODE=classic gnome-session
+TryExec=gnome-session
+Type=Application
+DesktopNames=GNOME-Classic;GNOME;
+X-GDM-SessionRegisters=true
+"#;
+
+    static SWAY: &str = r#"[Desktop Entry]
+Name=Sway
+Comment=An i3-compatible Wayland compositor
+Exec=sway
+Type=Application
+"#;
+
+    static I3: &str = r#"[Desktop Entry]
+Name=i3
+Comment=improved dynamic tiling window manager
+Exec=i3
+TryExec=i3
+Type=Application
+X-LightDM-DesktopName=i3
+DesktopNames=i3
+Keywords=tiling;wm;windowmanager;window;manager;
+"#;
+
+    static XFCE: &str = r#"[Desktop Entry]
+Version=1.0
+Name=Xfce Session
+Comment=Use this session to run Xfce as your desktop environment
+Exec=startxfce4
+Icon=
+Type=Application
+DesktopNames=XFCE
+Keywords=xfce;gtk;desktop;environment;
+"#;
+
+    #[test]
+    fn parses_plasma() {
+        let entry = DesktopEntry::parse(PLASMA).unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+
+        assert_eq!(group.string("Name").unwrap(), "Plasma (Wayland)");
+        assert_eq!(group.get("Comment[fr]"), Some("Plasma par KDE"));
+        assert_eq!(
+            group.exec().unwrap().unwrap(),
+            ["/usr/lib/plasma-dbus-run-session-if-needed", "/usr/bin/startplasma-wayland"]
+        );
+        assert_eq!(group.strings("DesktopNames").unwrap(), ["KDE"]);
+    }
+
+    #[test]
+    fn parses_gnome_classic() {
+        let entry = DesktopEntry::parse(GNOME_CLASSIC).unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+
+        assert_eq!(
+            group.exec().unwrap().unwrap(),
+            ["env", "GNOME_SHELL_SESSION_MODE=classic", "gnome-session"]
+        );
+        // The trailing semicolon doesn't produce an empty desktop name
+        assert_eq!(group.strings("DesktopNames").unwrap(), ["GNOME-Classic", "GNOME"]);
+        assert_eq!(group.string("TryExec").unwrap(), "gnome-session");
+    }
+
+    #[test]
+    fn parses_sway_i3_and_xfce() {
+        for (contents, exec, desktop_names) in
+            [(SWAY, "sway", None), (I3, "i3", Some("i3")), (XFCE, "startxfce4", Some("XFCE"))]
+        {
+            let entry = DesktopEntry::parse(contents).unwrap();
+            let group = entry.group("Desktop Entry").unwrap();
+
+            assert_eq!(group.exec().unwrap().unwrap(), [exec]);
+            assert_eq!(
+                group.strings("DesktopNames"),
+                desktop_names.map(|name| vec![name.to_owned()])
+            );
+        }
+    }
+
+    #[test]
+    fn unescapes_strings_and_lists() {
+        let entry = DesktopEntry::parse(
+            "[Desktop Entry]\nName=Two\\sWords\\nand\\ta\\\\backslash\nDesktopNames=a\\;b;c;\n",
+        )
+        .unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+
+        assert_eq!(group.string("Name").unwrap(), "Two Words\nand\ta\\backslash");
+        assert_eq!(group.strings("DesktopNames").unwrap(), ["a;b", "c"]);
+    }
+
+    #[test]
+    fn splits_quoted_exec_and_strips_field_codes() {
+        assert_eq!(
+            split_exec(r#""/opt/My Session/bin/start" --name "say \"hi\"" %U 100%% --file=%f"#)
+                .unwrap(),
+            ["/opt/My Session/bin/start", "--name", "say \"hi\"", "100%", "--file="]
+        );
+
+        // Escaped backslashes are unescaped once as a string, then again as Exec=
+        let entry =
+            DesktopEntry::parse("[Desktop Entry]\nExec=\"/usr/bin/a\\\\\\\\b\" %F\n").unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+        assert_eq!(group.exec().unwrap().unwrap(), ["/usr/bin/a\\b"]);
+
+        assert!(split_exec("\"unterminated").is_err());
+        assert!(split_exec("sway %z").is_err());
+        assert!(split_exec("%U").is_err());
+    }
+
+    #[test]
+    fn handles_comments_and_other_groups() {
+        let entry = DesktopEntry::parse(
+            "# A comment\n\n[Desktop Entry]\n# Another = comment\nName=Test\n\n[Desktop Action new]\nName=New\n",
+        )
+        .unwrap();
+
+        assert_eq!(entry.group("Desktop Entry").unwrap().string("Name").unwrap(), "Test");
+        assert_eq!(entry.group("Desktop Action new").unwrap().string("Name").unwrap(), "New");
+        assert!(entry.group("Missing").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(DesktopEntry::parse("Name=Orphan\n[Desktop Entry]\n").is_err());
+        assert!(DesktopEntry::parse("[Desktop Entry]\n[Desktop Entry]\n").is_err());
+        assert!(DesktopEntry::parse("[Desktop Entry]\nName=A\nName=B\n").is_err());
+        assert!(DesktopEntry::parse("[Desktop Entry]\nNot a key value pair\n").is_err());
+        assert!(DesktopEntry::parse("[Desktop Entry]\nName[=A\n").is_err());
+    }
+
+    #[test]
+    fn parses_booleans() {
+        let entry =
+            DesktopEntry::parse("[Desktop Entry]\nHidden=true\nNoDisplay=false\nTerminal=yes\n")
+                .unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+
+        assert_eq!(group.boolean("Hidden").unwrap(), Some(true));
+        assert_eq!(group.boolean("NoDisplay").unwrap(), Some(false));
+        assert_eq!(group.boolean("Missing").unwrap(), None);
+        assert!(group.boolean("Terminal").is_err());
+    }
+
+    #[test]
+    fn parses_locales() {
+        let locale = |lang: &str, country: Option<&str>, modifier: Option<&str>| Locale {
+            lang: lang.to_owned(),
+            country: country.map(str::to_owned),
+            modifier: modifier.map(str::to_owned),
+        };
+
+        assert_eq!(Locale::parse("de"), Some(locale("de", None, None)));
+        assert_eq!(Locale::parse("en_US.UTF-8"), Some(locale("en", Some("US"), None)));
+        assert_eq!(
+            Locale::parse("sr_RS.UTF-8@latin"),
+            Some(locale("sr", Some("RS"), Some("latin")))
+        );
+        assert_eq!(Locale::parse("C.UTF-8"), None);
+        assert_eq!(Locale::parse("POSIX"), None);
+        assert_eq!(Locale::parse(""), None);
+    }
+
+    #[test]
+    fn picks_best_localized_string() {
+        let entry = DesktopEntry::parse(
+            "[Desktop Entry]\nName=Default\nName[sr]=Lang\nName[sr@latin]=Modifier\nName[sr_RS]=Country\nName[sr_RS@latin]=Both\nName[zh_CN]=Chinese\n",
+        )
+        .unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+        let name = |locale: &str| group.localized_string("Name", Locale::parse(locale).as_ref());
+
+        assert_eq!(name("sr_RS.UTF-8@latin").unwrap(), "Both");
+        assert_eq!(name("sr_RS.UTF-8").unwrap(), "Country");
+        assert_eq!(name("sr_ME@latin").unwrap(), "Modifier");
+        assert_eq!(name("sr_ME").unwrap(), "Lang");
+        assert_eq!(name("zh_CN.UTF-8").unwrap(), "Chinese");
+        // Country-specific translations aren't used for other countries
+        assert_eq!(name("zh_TW").unwrap(), "Default");
+        assert_eq!(name("C").unwrap(), "Default");
+
+        let entry = DesktopEntry::parse(PLASMA).unwrap();
+        let group = entry.group("Desktop Entry").unwrap();
+        let comment = group.localized_string("Comment", Locale::parse("fr_CA.UTF-8").as_ref());
+        assert_eq!(comment.unwrap(), "Plasma par KDE");
+        assert_eq!(group.localized_string("Missing", Locale::parse("fr").as_ref()), None);
+    }
+}
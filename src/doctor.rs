@@ -0,0 +1,241 @@
+//! The `doctor` subcommand, which checks everything the greeter depends on
+//! and reports what's wrong, for when a machine boots to a broken greeter.
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::args::get_args;
+use crate::config::{self, get_config, Config};
+use crate::greetd::transport::GreetdTransport;
+use crate::sessions::{self, Status};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    /// Worth knowing about, but the greeter will still work.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, outcome: Outcome, subject: impl Display, detail: impl Display) {
+        let label = match outcome {
+            Outcome::Ok => "ok",
+            Outcome::Warn => "warn",
+            Outcome::Fail => {
+                self.failures += 1;
+                "FAIL"
+            }
+        };
+        println!("{:<5} {}: {}", label, subject, detail);
+    }
+}
+
+/// Works out what kind of image `contents` is from its first few bytes, if it's
+/// one the webview can show.
+fn image_format(contents: &[u8]) -> Option<&'static str> {
+    let text = String::from_utf8_lossy(&contents[..contents.len().min(1024)]);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+
+    if contents.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("PNG")
+    } else if contents.starts_with(b"\xff\xd8\xff") {
+        Some("JPEG")
+    } else if contents.starts_with(b"GIF87a") || contents.starts_with(b"GIF89a") {
+        Some("GIF")
+    } else if contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP") {
+        Some("WebP")
+    } else if contents.get(4..12).is_some_and(|brand| brand == b"ftypavif") {
+        Some("AVIF")
+    } else if contents.starts_with(b"BM") {
+        Some("BMP")
+    } else if text.starts_with("<svg") || text.starts_with("<!DOCTYPE svg") {
+        Some("SVG")
+    } else if (text.starts_with("<?xml") || text.starts_with("<!--")) && text.contains("<svg") {
+        // The root element may come after an XML declaration, comments and a
+        // doctype
+        Some("SVG")
+    } else {
+        None
+    }
+}
+
+fn check_image(report: &mut Report, name: &str, path: Option<&Path>) {
+    let Some(path) = path else {
+        report.check(Outcome::Ok, name, "none set");
+        return;
+    };
+
+    match std::fs::read(path) {
+        Err(error) => report.check(
+            Outcome::Fail,
+            name,
+            format!("failed to read {}: {}", path.display(), error),
+        ),
+        Ok(contents) => match image_format(&contents) {
+            Some(format) => {
+                report.check(Outcome::Ok, name, format!("{} ({})", path.display(), format))
+            }
+            None => report.check(
+                Outcome::Fail,
+                name,
+                format!("{} isn't an image format the greeter can show", path.display()),
+            ),
+        },
+    }
+}
+
+fn check_config(report: &mut Report) {
+    let config = match config::load() {
+        Ok(config) => {
+            let detail = match Config::path() {
+                Some(path) => format!("loaded {}", path.display()),
+                None => "no config file, using the defaults".to_owned(),
+            };
+            report.check(Outcome::Ok, "config", detail);
+            config
+        }
+        Err(error) => {
+            report.check(Outcome::Fail, "config", format!("{:#}", error));
+            // Carry on with the defaults, so that the rest can still be checked
+            let mut config = Config::default();
+            config.apply_args(get_args());
+            config
+        }
+    };
+    config::init(config);
+}
+
+fn check_sessions(report: &mut Report) {
+    let config = get_config();
+    let icon_theme = config.icon_theme.as_deref();
+    let custom_sessions: Vec<_> =
+        config.sessions.iter().map(|session| session.to_session(icon_theme)).collect();
+    let mut offered = HashSet::new();
+
+    for candidate in sessions::discover_sessions(&custom_sessions, &config.session_dirs, icon_theme)
+    {
+        let subject = format!("session {}", candidate.source);
+        match candidate.status {
+            Status::Offered(session) => {
+                report.check(Outcome::Ok, subject, format!("offers {}", session.slug));
+                offered.insert(session.slug);
+            }
+            Status::Shadowed { session, by } => report.check(
+                Outcome::Ok,
+                subject,
+                format!("{} is shadowed by {}", session.slug, by),
+            ),
+            Status::Unavailable { session, reason } => report.check(
+                Outcome::Warn,
+                subject,
+                format!("{} is unavailable: {:#}", session.slug, reason),
+            ),
+            Status::Invalid(error) => report.check(Outcome::Fail, subject, format!("{:#}", error)),
+        }
+    }
+
+    if offered.is_empty() {
+        report.check(Outcome::Fail, "sessions", "no sessions to offer");
+    }
+
+    match &config.default_session {
+        None => report.check(Outcome::Ok, "default session", "none set"),
+        Some(slug) if offered.contains(slug) => report.check(Outcome::Ok, "default session", slug),
+        Some(slug) => report.check(
+            Outcome::Fail,
+            "default session",
+            format!("{} isn't one of the sessions offered", slug),
+        ),
+    }
+}
+
+fn check_greetd(report: &mut Report) {
+    // greetd only sets GREETD_SOCK for the greeter itself, so it's usually
+    // missing when this is run by hand
+    let config = get_config();
+    let Some(socket_path) = &config.greetd_sock else {
+        report.check(
+            Outcome::Warn,
+            "greetd",
+            "no socket given; pass --greetd-sock to check the connection",
+        );
+        return;
+    };
+
+    match GreetdTransport::connect(socket_path) {
+        Ok(_) => {
+            report.check(Outcome::Ok, "greetd", format!("connected to {}", socket_path.display()))
+        }
+        Err(error) => {
+            report.check(Outcome::Fail, "greetd", format!("{}: {:#}", socket_path.display(), error))
+        }
+    }
+}
+
+/// Runs every check, printing the results. Returns whether they all passed.
+pub fn run() -> bool {
+    let mut report = Report::default();
+
+    check_config(&mut report);
+    check_image(&mut report, "background", get_config().background.as_deref());
+    check_image(&mut report, "logo", get_config().logo.as_deref());
+    check_sessions(&mut report);
+    check_greetd(&mut report);
+
+    match report.failures {
+        0 => println!("\nNo problems found"),
+        1 => println!("\nFound 1 problem"),
+        failures => println!("\nFound {} problems", failures),
+    }
+    report.failures == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_raster_images() {
+        let cases: [(&[u8], _); 6] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "PNG"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", "JPEG"),
+            (b"GIF89a\x01\0\x01\0", "GIF"),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", "WebP"),
+            (b"\0\0\0\x1cftypavif\0\0\0\0", "AVIF"),
+            (b"BM\x36\0\0\0", "BMP"),
+        ];
+        for (contents, format) in cases {
+            assert_eq!(image_format(contents), Some(format));
+        }
+    }
+
+    #[test]
+    fn recognises_svg() {
+        let cases = [
+            "<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            "\u{feff}\n  <svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            "<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            "<!-- Created with Inkscape -->\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            "<!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \"\">\n<svg/>",
+        ];
+        for contents in cases {
+            assert_eq!(image_format(contents.as_bytes()), Some("SVG"), "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        for contents in
+            ["", "plain text", "<?xml version=\"1.0\"?>\n<html/>", "<html><svg/></html>"]
+        {
+            assert_eq!(image_format(contents.as_bytes()), None, "{:?}", contents);
+        }
+    }
+}
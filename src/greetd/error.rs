@@ -0,0 +1,128 @@
+use std::fmt::Display;
+
+use color_eyre::eyre::Report;
+
+use super::transport::Unreachable;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while talking to greetd, split up by who is at
+/// fault so that the UI can react accordingly.
+#[derive(Debug)]
+pub enum Error {
+    /// greetd rejected the user's answers, e.g. a wrong password.
+    Auth(String),
+    /// greetd failed for some other reason, e.g. a session command that
+    /// couldn't be started.
+    Greetd(String),
+    /// greetd couldn't be reached at all, even after retrying.
+    Unavailable(Report),
+    /// The transport couldn't be set up, e.g. because no socket was given.
+    /// Unlike [`Error::Unavailable`], retrying won't help.
+    Setup(Report),
+    /// The connection to greetd failed.
+    Transport(Report),
+    /// greetd answered with something that makes no sense at this point in the
+    /// conversation.
+    Protocol(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auth(description) => write!(f, "authentication failed: {}", description),
+            Self::Greetd(description) => write!(f, "greetd error: {}", description),
+            Self::Unavailable(report) => write!(f, "greetd unavailable: {:#}", report),
+            Self::Setup(report) => write!(f, "failed to set up transport: {:#}", report),
+            Self::Transport(report) => write!(f, "transport error: {:#}", report),
+            Self::Protocol(description) => write!(f, "protocol violation: {}", description),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Report> for Error {
+    fn from(report: Report) -> Self {
+        Self::Transport(report)
+    }
+}
+
+/// Whether an auth error's description says nothing more than that the answers
+/// were wrong, like PAM's `AUTH_ERR` that greetd passes on, as opposed to e.g.
+/// the account being locked.
+fn is_generic_auth_failure(description: &str) -> bool {
+    let description = description.trim();
+    description.is_empty()
+        || description.eq_ignore_ascii_case("authentication failed")
+        || description.contains("AUTH_ERR")
+}
+
+impl Error {
+    /// Sorts out why a transport couldn't be created, so that only failures to
+    /// reach greetd are retried.
+    pub fn from_new_transport(report: Report) -> Self {
+        if report.downcast_ref::<Unreachable>().is_some() {
+            Self::Unavailable(report)
+        } else {
+            Self::Setup(report)
+        }
+    }
+
+    /// A message suitable for showing to the person trying to log in.
+    pub fn user_message(&self) -> String {
+        match self {
+            Self::Auth(description) if !is_generic_auth_failure(description) => description.clone(),
+            Self::Auth(_) => "Incorrect password. Please try again.".to_owned(),
+            Self::Greetd(description) => format!("Couldn't log in: {}", description),
+            Self::Unavailable(_) => "Couldn't connect to the login service.".to_owned(),
+            Self::Setup(report) => format!("Couldn't connect to the login service: {:#}", report),
+            Self::Transport(_) => {
+                "Couldn't reach the login service. Please try again in a moment.".to_owned()
+            }
+            Self::Protocol(_) => "Something went wrong talking to the login service.".to_owned(),
+        }
+    }
+
+    /// Whether it's worth retrying with the same username, as opposed to
+    /// starting over from scratch.
+    pub fn keeps_username(&self) -> bool {
+        matches!(self, Self::Auth(_) | Self::Unavailable(_) | Self::Transport(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_only_unreachable_transports() {
+        let error = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let report = Report::new(error)
+            .wrap_err("failed to connect to greetd")
+            .wrap_err(Unreachable { attempts: 5 });
+        assert!(matches!(Error::from_new_transport(report), Error::Unavailable(_)));
+
+        let report = color_eyre::eyre::eyre!("no greetd socket given");
+        let error = Error::from_new_transport(report);
+        assert!(matches!(error, Error::Setup(_)));
+        assert_eq!(
+            error.user_message(),
+            "Couldn't connect to the login service: no greetd socket given"
+        );
+    }
+
+    #[test]
+    fn shows_specific_auth_errors() {
+        let error = Error::Auth("Account locked due to too many failed attempts".to_owned());
+        assert_eq!(error.user_message(), "Account locked due to too many failed attempts");
+    }
+
+    #[test]
+    fn hides_generic_auth_errors() {
+        for description in ["", "authentication failed", "pam_authenticate: AUTH_ERR"] {
+            let error = Error::Auth(description.to_owned());
+            assert_eq!(error.user_message(), "Incorrect password. Please try again.");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! A stand-in for greetd that speaks the real IPC protocol over a Unix socket.
+//!
+//! Each connection gets its own [`Player`] for the given [`Scenario`], so the
+//! real greeter can be run against it with `GREETD_SOCK` pointed at the
+//! socket, without root or PAM.
+
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+use color_eyre::eyre::{Context, Result};
+use greetd_ipc::codec::{Error as CodecError, SyncCodec};
+use greetd_ipc::Request;
+
+use super::scenario::{Player, Scenario};
+
+/// Listens on `socket_path` and serves connections until killed.
+pub fn run(socket_path: &Path, scenario_path: Option<&Path>) -> Result<()> {
+    let scenario = match scenario_path {
+        Some(path) => Scenario::load(path)?,
+        None => Scenario::default(),
+    };
+
+    let listener = UnixListener::bind(socket_path)
+        .wrap_err_with(|| format!("failed to bind {}", socket_path.display()))?;
+
+    println!("Listening on {}", socket_path.display());
+    serve(listener, scenario)
+}
+
+pub fn serve(listener: UnixListener, scenario: Scenario) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream.wrap_err("failed to accept connection")?;
+        let player = Player::new(scenario.clone());
+
+        std::thread::spawn(move || {
+            if let Err(error) = handle_connection(stream, player) {
+                eprintln!("Connection failed: {:?}", error);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(mut stream: UnixStream, mut player: Player) -> Result<()> {
+    loop {
+        let request = match Request::read_from(&mut stream) {
+            Ok(request) => request,
+            Err(CodecError::Eof) => return Ok(()),
+            Err(error) => return Err(error).wrap_err("failed to read request"),
+        };
+
+        let response = player.handle(&request);
+        response.write_to(&mut stream).wrap_err("failed to write response")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::greetd::error::{self, Error};
+    use crate::greetd::session_builder::{self, Message, SessionBuilder};
+    use crate::greetd::transport::GreetdTransport;
+
+    static SCENARIO: &str = r#"
+        [default]
+        steps = [
+            { kind = "prompt", type = "secret", message = "Password: ", expect = "hunter2" },
+            { kind = "prompt", type = "info", message = "Welcome!" },
+        ]
+
+        [users.nopass]
+        start_session_error = { error_type = "error", description = "no such command" }
+    "#;
+
+    /// Starts a fake greetd in the background, returning the socket path. The
+    /// temporary directory holding the socket must outlive the test.
+    fn start_server() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("greetd.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let scenario = toml::from_str(SCENARIO).unwrap();
+        std::thread::spawn(move || serve(listener, scenario));
+        (dir, socket_path)
+    }
+
+    fn create_session(
+        socket_path: &Path,
+        username: &str,
+    ) -> error::Result<SessionBuilder<GreetdTransport>> {
+        let transport = GreetdTransport::connect(socket_path)?;
+        session_builder::create_session_with(transport, username.to_owned())
+    }
+
+    #[test]
+    fn creates_and_starts_session() {
+        let (_dir, socket_path) = start_server();
+
+        let Ok(SessionBuilder::NeedAuthResponse(builder)) = create_session(&socket_path, "alice")
+        else {
+            panic!("expected a password prompt");
+        };
+        assert_eq!(builder.auth_message, "Password: ");
+
+        let Ok(SessionBuilder::SessionCreated(builder)) =
+            builder.post_auth_message_response(Some("hunter2".to_owned()))
+        else {
+            panic!("expected the session to be created");
+        };
+        assert!(matches!(&builder.messages[..], [Message::Info(message)] if message == "Welcome!"));
+
+        builder.start_session(vec!["sway".to_owned()], vec![]).unwrap();
+    }
+
+    #[test]
+    fn rejects_wrong_password() {
+        let (_dir, socket_path) = start_server();
+
+        let Ok(SessionBuilder::NeedAuthResponse(builder)) = create_session(&socket_path, "alice")
+        else {
+            panic!("expected a password prompt");
+        };
+
+        let error = builder.post_auth_message_response(Some("hunter3".to_owned())).unwrap_err();
+        assert!(matches!(error, Error::Auth(_)));
+    }
+
+    #[test]
+    fn reports_start_session_error() {
+        let (_dir, socket_path) = start_server();
+
+        let Ok(SessionBuilder::SessionCreated(builder)) = create_session(&socket_path, "nopass")
+        else {
+            panic!("expected the session to be created");
+        };
+
+        let error = builder.start_session(vec!["sway".to_owned()], vec![]).unwrap_err();
+        assert!(matches!(error, Error::Greetd(description) if description == "no such command"));
+    }
+}
@@ -1,2 +1,5 @@
+pub mod error;
+pub mod fake_server;
+pub mod scenario;
 pub mod session_builder;
 pub mod transport;
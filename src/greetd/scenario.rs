@@ -0,0 +1,421 @@
+//! Scripted greetd conversations.
+//!
+//! A [`Scenario`] describes, per username, the sequence of auth messages and
+//! errors greetd would send, so that demo mode can reproduce any real PAM
+//! conversation without a running greetd. Scenarios are written in TOML; see
+//! `default_scenario.toml` for the one used when no file is given.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use greetd_ipc::{AuthMessageType, ErrorType, Request, Response};
+use serde::Deserialize;
+
+static DEFAULT_SCENARIO: &str = include_str!("default_scenario.toml");
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// The conversation for any username not listed in `users`.
+    #[serde(default)]
+    pub default: Conversation,
+
+    #[serde(default)]
+    pub users: HashMap<String, Conversation>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Conversation {
+    /// Auth messages and errors sent in order, one per request. Once all steps
+    /// have been answered, the session is considered created.
+    #[serde(default)]
+    pub steps: Vec<Step>,
+
+    /// An error to answer `start_session` with instead of success.
+    pub start_session_error: Option<InjectedError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    Prompt {
+        r#type: PromptType,
+        message: String,
+        /// The answer required to continue. Any other answer fails
+        /// authentication. If omitted, every answer is accepted.
+        expect: Option<String>,
+        #[serde(default)]
+        delay_ms: u64,
+    },
+    Error {
+        error_type: InjectedErrorType,
+        description: String,
+        #[serde(default)]
+        delay_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InjectedError {
+    pub error_type: InjectedErrorType,
+    pub description: String,
+}
+
+/// Mirrors [`AuthMessageType`], which isn't `Copy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptType {
+    Visible,
+    Secret,
+    Info,
+    Error,
+}
+
+/// Mirrors [`ErrorType`], which isn't `Copy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectedErrorType {
+    Error,
+    AuthError,
+}
+
+impl From<PromptType> for AuthMessageType {
+    fn from(value: PromptType) -> Self {
+        match value {
+            PromptType::Visible => Self::Visible,
+            PromptType::Secret => Self::Secret,
+            PromptType::Info => Self::Info,
+            PromptType::Error => Self::Error,
+        }
+    }
+}
+
+impl From<InjectedErrorType> for ErrorType {
+    fn from(value: InjectedErrorType) -> Self {
+        match value {
+            InjectedErrorType::Error => Self::Error,
+            InjectedErrorType::AuthError => Self::AuthError,
+        }
+    }
+}
+
+impl InjectedError {
+    pub fn to_response(&self) -> Response {
+        Response::Error {
+            error_type: self.error_type.into(),
+            description: self.description.clone(),
+        }
+    }
+}
+
+impl Step {
+    pub fn delay(&self) -> Duration {
+        match self {
+            Self::Prompt { delay_ms, .. } | Self::Error { delay_ms, .. } => {
+                Duration::from_millis(*delay_ms)
+            }
+        }
+    }
+
+    pub fn to_response(&self) -> Response {
+        match self {
+            Self::Prompt { r#type, message, .. } => Response::AuthMessage {
+                auth_message_type: (*r#type).into(),
+                auth_message: message.clone(),
+            },
+            Self::Error { error_type, description, .. } => Response::Error {
+                error_type: (*error_type).into(),
+                description: description.clone(),
+            },
+        }
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        toml::from_str(DEFAULT_SCENARIO).expect("built-in scenario should be valid")
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read scenario {}", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("failed to parse scenario {}", path.display()))
+    }
+
+    pub fn conversation(&self, username: &str) -> &Conversation {
+        self.users.get(username).unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug)]
+enum PlayerState {
+    Idle,
+    /// Waiting for the answer to `steps[next - 1]`, which is always a prompt.
+    Authenticating {
+        username: String,
+        next: usize,
+    },
+    Authenticated {
+        username: String,
+    },
+}
+
+/// Plays a [`Scenario`] back one request at a time, the way greetd would.
+#[derive(Debug)]
+pub struct Player {
+    scenario: Scenario,
+    state: PlayerState,
+}
+
+fn error(error_type: ErrorType, description: &str) -> Response {
+    Response::Error { error_type, description: description.to_owned() }
+}
+
+impl Player {
+    pub fn new(scenario: Scenario) -> Self {
+        Self { scenario, state: PlayerState::Idle }
+    }
+
+    pub fn handle(&mut self, request: &Request) -> Response {
+        use PlayerState::*;
+
+        match (request, std::mem::replace(&mut self.state, Idle)) {
+            (Request::CreateSession { username }, Idle) => self.advance(username.clone(), 0),
+
+            (Request::PostAuthMessageResponse { response }, Authenticating { username, next }) => {
+                let conversation = self.scenario.conversation(&username);
+                match &conversation.steps[next - 1] {
+                    Step::Prompt { expect: Some(expected), .. }
+                        if response.as_ref() != Some(expected) =>
+                    {
+                        error(ErrorType::AuthError, "authentication failed")
+                    }
+                    _ => self.advance(username, next),
+                }
+            }
+
+            (Request::StartSession { cmd, env }, Authenticated { username }) => {
+                let conversation = self.scenario.conversation(&username);
+                let response = match &conversation.start_session_error {
+                    Some(error) => error.to_response(),
+                    None => {
+                        println!("Session started. cmd: {:?}, env: {:?}", cmd, env);
+                        Response::Success
+                    }
+                };
+                self.state = Authenticated { username };
+                response
+            }
+
+            (Request::CancelSession, _) => Response::Success,
+
+            (request, state) => {
+                let description = match request {
+                    Request::CreateSession { .. } => "a session is already being configured",
+                    Request::PostAuthMessageResponse { .. } => "no auth message pending",
+                    _ => "session not yet authenticated",
+                };
+                self.state = state;
+                error(ErrorType::Error, description)
+            }
+        }
+    }
+
+    /// Sends the step at index `next` of the user's conversation, or reports
+    /// success if there are none left.
+    fn advance(&mut self, username: String, next: usize) -> Response {
+        let Some(step) = self.scenario.conversation(&username).steps.get(next) else {
+            self.state = PlayerState::Authenticated { username };
+            return Response::Success;
+        };
+
+        std::thread::sleep(step.delay());
+        if let Step::Prompt { .. } = step {
+            self.state = PlayerState::Authenticating { username, next: next + 1 };
+        }
+        step.to_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SCENARIO: &str = r#"
+        [default]
+        steps = [
+            { kind = "prompt", type = "secret", message = "Password: ", expect = "hunter2" },
+            { kind = "prompt", type = "info", message = "Welcome!" },
+        ]
+
+        [users.locked]
+        steps = [
+            { kind = "prompt", type = "secret", message = "Password: " },
+            { kind = "error", error_type = "auth_error", description = "Account locked" },
+        ]
+
+        [users.nopass]
+        start_session_error = { error_type = "error", description = "no such command" }
+    "#;
+
+    fn player() -> Player {
+        Player::new(toml::from_str(SCENARIO).unwrap())
+    }
+
+    fn create_session(username: &str) -> Request {
+        Request::CreateSession { username: username.to_owned() }
+    }
+
+    fn answer(response: &str) -> Request {
+        Request::PostAuthMessageResponse { response: Some(response.to_owned()) }
+    }
+
+    fn start_session() -> Request {
+        Request::StartSession { cmd: vec!["sway".to_owned()], env: vec![] }
+    }
+
+    fn assert_prompt(response: Response, expected_type: AuthMessageType, expected_message: &str) {
+        match response {
+            Response::AuthMessage { auth_message_type, auth_message } => {
+                assert_eq!(
+                    std::mem::discriminant(&auth_message_type),
+                    std::mem::discriminant(&expected_type)
+                );
+                assert_eq!(auth_message, expected_message);
+            }
+            response => panic!("expected a prompt, got {:?}", response),
+        }
+    }
+
+    fn assert_error(response: Response, expected_type: ErrorType, expected_description: &str) {
+        match response {
+            Response::Error { error_type, description } => {
+                assert_eq!(
+                    std::mem::discriminant(&error_type),
+                    std::mem::discriminant(&expected_type)
+                );
+                assert_eq!(description, expected_description);
+            }
+            response => panic!("expected an error, got {:?}", response),
+        }
+    }
+
+    #[test]
+    fn parses_default_scenario() {
+        let scenario = Scenario::default();
+
+        assert_eq!(scenario.default.steps.len(), 2);
+        assert!(matches!(
+            &scenario.conversation("otp").steps[1],
+            Step::Prompt { r#type: PromptType::Visible, expect: Some(expect), delay_ms: 1000, .. }
+                if expect == "123456"
+        ));
+        assert!(matches!(
+            &scenario.conversation("locked").steps[1],
+            Step::Error { error_type: InjectedErrorType::AuthError, .. }
+        ));
+        // Unlisted users get the default conversation
+        assert_eq!(scenario.conversation("someone").steps.len(), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let error = toml::from_str::<Scenario>(
+            r#"
+            [default]
+            steps = [{ kind = "prompt", type = "secret", mesage = "Password: " }]
+            "#,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("mesage"));
+    }
+
+    #[test]
+    fn steps_through_conversation() {
+        let mut player = player();
+
+        assert_prompt(
+            player.handle(&create_session("alice")),
+            AuthMessageType::Secret,
+            "Password: ",
+        );
+        assert_prompt(player.handle(&answer("hunter2")), AuthMessageType::Info, "Welcome!");
+        assert!(matches!(player.handle(&answer("")), Response::Success));
+        assert!(matches!(player.handle(&start_session()), Response::Success));
+    }
+
+    #[test]
+    fn rejects_unexpected_answer() {
+        let mut player = player();
+
+        player.handle(&create_session("alice"));
+        assert_error(
+            player.handle(&answer("hunter3")),
+            ErrorType::AuthError,
+            "authentication failed",
+        );
+
+        // greetd forgets the session after failed authentication
+        assert_prompt(
+            player.handle(&create_session("alice")),
+            AuthMessageType::Secret,
+            "Password: ",
+        );
+    }
+
+    #[test]
+    fn sends_error_step() {
+        let mut player = player();
+
+        player.handle(&create_session("locked"));
+        assert_error(player.handle(&answer("anything")), ErrorType::AuthError, "Account locked");
+    }
+
+    #[test]
+    fn injects_start_session_error() {
+        let mut player = player();
+
+        assert!(matches!(player.handle(&create_session("nopass")), Response::Success));
+        assert_error(player.handle(&start_session()), ErrorType::Error, "no such command");
+    }
+
+    #[test]
+    fn rejects_requests_past_end_of_conversation() {
+        let mut player = player();
+
+        player.handle(&create_session("nopass"));
+        assert_error(player.handle(&answer("")), ErrorType::Error, "no auth message pending");
+        assert_error(
+            player.handle(&create_session("nopass")),
+            ErrorType::Error,
+            "a session is already being configured",
+        );
+        // The session is still there to be started, which fails as scripted
+        assert_error(player.handle(&start_session()), ErrorType::Error, "no such command");
+    }
+
+    #[test]
+    fn rejects_start_session_before_authentication() {
+        let mut player = player();
+
+        assert_error(
+            player.handle(&start_session()),
+            ErrorType::Error,
+            "session not yet authenticated",
+        );
+        player.handle(&create_session("alice"));
+        assert_error(
+            player.handle(&start_session()),
+            ErrorType::Error,
+            "session not yet authenticated",
+        );
+    }
+}
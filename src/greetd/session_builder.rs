@@ -8,28 +8,41 @@
 
 use std::fmt::Debug;
 
-use color_eyre::eyre::{bail, Result};
-use greetd_ipc::{AuthMessageType, Response};
+use greetd_ipc::{AuthMessageType, ErrorType, Response};
 
+use super::error::{Error, Result};
 use super::transport::Transport;
 
 #[derive(Debug)]
 pub enum AnsweredQuestion {
     Visible(String),
-    Secret(String),
+    /// Only the length of secret answers is kept, so that they can be shown as
+    /// a row of dots without holding on to the secret itself.
+    Secret(usize),
+}
+
+/// An info or error message from PAM. These don't need an answer, so they are
+/// acknowledged right away and collected to be shown alongside the next prompt.
+#[derive(Debug)]
+pub enum Message {
+    Info(String),
+    Error(String),
 }
 
 #[derive(Debug)]
 pub struct NeedAuthResponse<T: Transport> {
+    /// Either [`AuthMessageType::Visible`] or [`AuthMessageType::Secret`].
     pub auth_message_type: greetd_ipc::AuthMessageType,
     pub auth_message: String,
     pub prev_answers: Vec<AnsweredQuestion>,
+    pub messages: Vec<Message>,
     transport: T,
 }
 
 #[derive(Debug)]
 pub struct SessionCreated<T: Transport> {
     pub prev_answers: Vec<AnsweredQuestion>,
+    pub messages: Vec<Message>,
     transport: T,
 }
 
@@ -41,34 +54,58 @@ pub enum SessionBuilder<T: Transport> {
 
 /// [`create_session`] and [`post_auth_message_response`] handle the responses
 /// from greetd in a very similar way — either the session was created
-/// successfully, or there is an auth message to respond to.
+/// successfully, or there is an auth message to respond to. Info and error
+/// messages are acknowledged on the spot until one of those happens.
 ///
 /// This logic is factored into this function.
 fn handle_auth_message_response<T>(
     mut transport: T,
     prev_answers: Vec<AnsweredQuestion>,
-    response: Response,
+    mut messages: Vec<Message>,
+    mut response: Response,
 ) -> Result<SessionBuilder<T>>
 where
     T: Transport,
 {
-    Ok(match response {
-        Response::Success => {
-            SessionBuilder::SessionCreated(SessionCreated { transport, prev_answers })
-        }
-        Response::AuthMessage { auth_message_type, auth_message } => {
-            SessionBuilder::NeedAuthResponse(NeedAuthResponse {
-                auth_message_type,
-                auth_message,
-                transport,
-                prev_answers,
-            })
-        }
-        Response::Error { error_type, description } => {
-            transport.cancel_session()?;
-            bail!("{:?}: {}", error_type, description)
+    loop {
+        response = match response {
+            Response::Success => {
+                return Ok(SessionBuilder::SessionCreated(SessionCreated {
+                    transport,
+                    prev_answers,
+                    messages,
+                }));
+            }
+            Response::AuthMessage { auth_message_type: AuthMessageType::Info, auth_message } => {
+                messages.push(Message::Info(auth_message));
+                transport.post_auth_message_response(None)?
+            }
+            Response::AuthMessage { auth_message_type: AuthMessageType::Error, auth_message } => {
+                messages.push(Message::Error(auth_message));
+                transport.post_auth_message_response(None)?
+            }
+            Response::AuthMessage { auth_message_type, auth_message } => {
+                return Ok(SessionBuilder::NeedAuthResponse(NeedAuthResponse {
+                    auth_message_type,
+                    auth_message,
+                    transport,
+                    prev_answers,
+                    messages,
+                }));
+            }
+            Response::Error { error_type, description } => {
+                transport.cancel_session()?;
+                return Err(error_from_response(error_type, description));
+            }
         }
-    })
+    }
+}
+
+fn error_from_response(error_type: ErrorType, description: String) -> Error {
+    match error_type {
+        ErrorType::AuthError => Error::Auth(description),
+        ErrorType::Error => Error::Greetd(description),
+    }
 }
 
 /// Sends a request to create a session.
@@ -77,9 +114,17 @@ where
 /// - The session was created successfully.
 /// - There is an auth message to be answered.
 pub fn create_session<T: Transport>(username: String) -> Result<SessionBuilder<T>> {
-    let mut transport = T::new()?;
+    create_session_with(T::new().map_err(Error::from_new_transport)?, username)
+}
+
+/// Like [`create_session`], but over an already established transport.
+pub fn create_session_with<T: Transport>(
+    mut transport: T,
+    username: String,
+) -> Result<SessionBuilder<T>> {
     let response = transport.create_session(username.clone())?;
-    handle_auth_message_response(transport, vec![AnsweredQuestion::Visible(username)], response)
+    let prev_answers = vec![AnsweredQuestion::Visible(username)];
+    handle_auth_message_response(transport, prev_answers, Vec::new(), response)
 }
 
 impl<T: Transport> NeedAuthResponse<T> {
@@ -95,13 +140,13 @@ impl<T: Transport> NeedAuthResponse<T> {
         if let Some(response) = response.as_ref() {
             use AnsweredQuestion::*;
             match self.auth_message_type {
-                AuthMessageType::Secret => self.prev_answers.push(Secret(response.clone())),
+                AuthMessageType::Secret => self.prev_answers.push(Secret(response.chars().count())),
                 AuthMessageType::Visible => self.prev_answers.push(Visible(response.clone())),
                 _ => {}
             };
         };
         let response = self.transport.post_auth_message_response(response)?;
-        handle_auth_message_response(self.transport, self.prev_answers, response)
+        handle_auth_message_response(self.transport, self.prev_answers, self.messages, response)
     }
 }
 
@@ -115,9 +160,32 @@ impl<T: Transport> SessionCreated<T> {
             Response::Success => Ok(()),
             Response::Error { error_type, description } => {
                 self.transport.cancel_session()?;
-                bail!("{:?}: {}", error_type, description)
+                Err(error_from_response(error_type, description))
+            }
+            Response::AuthMessage { .. } => {
+                Err(Error::Protocol("unexpected auth_message after start_session".to_owned()))
+            }
+        }
+    }
+}
+
+impl<T: Transport> SessionBuilder<T> {
+    /// Cancels the session, e.g. because the user wants to start over with a
+    /// different username.
+    pub fn cancel(self) -> Result<()> {
+        let mut transport = match self {
+            Self::NeedAuthResponse(NeedAuthResponse { transport, .. })
+            | Self::SessionCreated(SessionCreated { transport, .. }) => transport,
+        };
+
+        match transport.cancel_session()? {
+            Response::Success => Ok(()),
+            Response::Error { error_type, description } => {
+                Err(error_from_response(error_type, description))
+            }
+            Response::AuthMessage { .. } => {
+                Err(Error::Protocol("unexpected auth_message after cancel_session".to_owned()))
             }
-            Response::AuthMessage { .. } => bail!("unexpected auth_message after start_session"),
         }
     }
 }
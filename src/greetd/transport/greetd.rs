@@ -1,23 +1,96 @@
 use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
 
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, OptionExt, Result};
 use greetd_ipc::codec::SyncCodec;
 use greetd_ipc::{Request, Response};
 
-use super::Transport;
+use super::{Transport, Unreachable};
+use crate::config::get_config;
+
+/// How long to wait before the second connection attempt. The delay doubles
+/// with every attempt after that.
+static INITIAL_BACKOFF: Duration = Duration::from_millis(200);
 
 #[derive(Debug)]
-pub struct GreetdTransport(UnixStream);
+pub struct GreetdTransport {
+    socket: UnixStream,
+}
+
+impl GreetdTransport {
+    pub fn connect(socket_path: impl AsRef<Path>) -> Result<Self> {
+        let socket = UnixStream::connect(socket_path).wrap_err("failed to connect to greetd")?;
+        Ok(Self { socket })
+    }
+
+    /// Sets how long to wait for greetd to accept or answer a request, or
+    /// removes the limit if `timeout` is `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.socket.set_read_timeout(timeout).wrap_err("failed to set read timeout")?;
+        self.socket.set_write_timeout(timeout).wrap_err("failed to set write timeout")?;
+        Ok(())
+    }
+}
 
 impl Transport for GreetdTransport {
     fn new() -> Result<Self> {
-        let socket_path = std::env::var("GREETD_SOCK").wrap_err("failed to read GREETD_SOCK")?;
-        let socket = UnixStream::connect(socket_path).wrap_err("failed to connect to greetd")?;
-        Ok(Self(socket))
+        let config = get_config();
+        let socket_path = config
+            .greetd_sock
+            .as_ref()
+            .ok_or_eyre("no greetd socket given; set GREETD_SOCK, pass --greetd-sock or set greetd_sock in the config")?;
+
+        let mut attempt = 1;
+        let mut backoff = INITIAL_BACKOFF;
+        let mut transport = loop {
+            match Self::connect(socket_path) {
+                Ok(transport) => break transport,
+                Err(error) if attempt >= config.connect_attempts => {
+                    return Err(error.wrap_err(Unreachable { attempts: attempt }));
+                }
+                Err(_) => {
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                    backoff *= 2;
+                }
+            }
+        };
+
+        // A timeout of 0 means waiting as long as it takes
+        let timeout =
+            (config.greetd_timeout > 0).then(|| Duration::from_secs(config.greetd_timeout));
+        transport.set_timeout(timeout)?;
+        Ok(transport)
     }
 
-    fn send_request(&mut self, request: Request) -> Result<Response> {
-        request.write_to(&mut self.0).wrap_err("failed to write to greetd")?;
-        Response::read_from(&mut self.0).wrap_err("failed to read from greetd")
+    // greetd keeps the session it's creating per connection, so a failed
+    // request isn't retried over a new one. The caller drops the transport and
+    // starts over instead.
+    fn send_request(&mut self, request: &Request) -> Result<Response> {
+        request.write_to(&mut self.socket).wrap_err("failed to write to greetd")?;
+        Response::read_from(&mut self.socket).wrap_err("failed to read from greetd")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn times_out_when_greetd_does_not_answer() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("greetd.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let mut transport = GreetdTransport::connect(&socket_path).unwrap();
+        transport.set_timeout(Some(Duration::from_millis(100))).unwrap();
+        // Accept the connection, but never answer it
+        let (_server, _) = listener.accept().unwrap();
+
+        let error = transport.cancel_session().unwrap_err();
+        assert_eq!(error.to_string(), "failed to read from greetd");
     }
 }
@@ -1,31 +1,63 @@
 mod greetd;
 mod mock;
+mod recording;
+mod replay;
+
+use std::fmt::Display;
 
 use color_eyre::eyre::Result;
 pub use greetd::GreetdTransport;
 use greetd_ipc::{Request, Response};
 pub use mock::MockTransport;
+pub use recording::RecordingTransport;
+pub use replay::ReplayTransport;
+use zeroize::Zeroize;
+
+/// Attached to the error from [`Transport::new`] when whatever the transport
+/// talks to couldn't be reached, as opposed to e.g. the transport not being
+/// set up right. Only the former is worth retrying.
+#[derive(Debug)]
+pub struct Unreachable {
+    pub attempts: u32,
+}
+
+impl Display for Unreachable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.attempts {
+            1 => write!(f, "gave up after 1 attempt"),
+            attempts => write!(f, "gave up after {} attempts", attempts),
+        }
+    }
+}
 
 pub trait Transport {
     fn new() -> Result<Self>
     where
         Self: Sized;
 
-    fn send_request(&mut self, request: Request) -> Result<Response>;
+    /// Requests are borrowed so that the caller can wipe any secrets in them
+    /// once they have been sent.
+    fn send_request(&mut self, request: &Request) -> Result<Response>;
 
     fn create_session(&mut self, username: String) -> Result<Response> {
-        self.send_request(Request::CreateSession { username })
+        self.send_request(&Request::CreateSession { username })
     }
 
+    /// The response is zeroized once sent, as it may well be a password.
     fn post_auth_message_response(&mut self, response: Option<String>) -> Result<Response> {
-        self.send_request(Request::PostAuthMessageResponse { response })
+        let mut request = Request::PostAuthMessageResponse { response };
+        let result = self.send_request(&request);
+        if let Request::PostAuthMessageResponse { response: Some(response) } = &mut request {
+            response.zeroize();
+        }
+        result
     }
 
     fn start_session(&mut self, cmd: Vec<String>, env: Vec<String>) -> Result<Response> {
-        self.send_request(Request::StartSession { cmd, env })
+        self.send_request(&Request::StartSession { cmd, env })
     }
 
     fn cancel_session(&mut self) -> Result<Response> {
-        self.send_request(Request::CancelSession)
+        self.send_request(&Request::CancelSession)
     }
 }
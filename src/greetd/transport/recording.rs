@@ -0,0 +1,140 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+use color_eyre::eyre::{Context, OptionExt, Result};
+use greetd_ipc::{AuthMessageType, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use super::Transport;
+use crate::args::get_args;
+
+/// Written in place of answers to secret prompts.
+static REDACTED: &str = "<redacted>";
+
+/// One line of a recording. Requests and responses are logged separately, so
+/// that a request greetd never answered still shows up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    pub event: Event,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// A new transport was created, i.e. a new connection to greetd.
+    Connected,
+    /// Kept as raw JSON, since secrets may have been redacted.
+    Request(serde_json::Value),
+    Response(serde_json::Value),
+    /// The wrapped transport failed to send the request or read a response.
+    Error(String),
+}
+
+/// Wraps another transport, logging every request and response to the file
+/// given to `--record`. Answers to secret prompts are redacted.
+#[derive(Debug)]
+pub struct RecordingTransport<T: Transport> {
+    inner: T,
+    log: File,
+    secret_prompt_pending: bool,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Wraps `inner`, logging to `log` from here on.
+    pub fn with_log(inner: T, log: File) -> Self {
+        let mut transport = Self { inner, log, secret_prompt_pending: false };
+        transport.record(Event::Connected);
+        transport
+    }
+
+    fn record(&mut self, event: Event) {
+        let entry = Entry { timestamp: chrono::Local::now().to_rfc3339(), event };
+
+        // A broken log shouldn't stop anyone from logging in
+        let result = serde_json::to_writer(&mut self.log, &entry)
+            .map_err(std::io::Error::from)
+            .and_then(|()| self.log.write_all(b"\n"));
+        if let Err(error) = result {
+            eprintln!("Failed to record greetd conversation: {}", error);
+        }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn new() -> Result<Self> {
+        let path = get_args().record.as_ref().ok_or_eyre("no recording path given")?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("failed to open recording {}", path.display()))?;
+
+        Ok(Self::with_log(T::new()?, log))
+    }
+
+    fn send_request(&mut self, request: &Request) -> Result<Response> {
+        let recorded = match request {
+            // Never let the secret itself anywhere near the log
+            Request::PostAuthMessageResponse { response: Some(_) }
+                if self.secret_prompt_pending =>
+            {
+                let redacted = Some(REDACTED.to_owned());
+                serde_json::to_value(Request::PostAuthMessageResponse { response: redacted })?
+            }
+            request => serde_json::to_value(request)?,
+        };
+        self.record(Event::Request(recorded));
+
+        let response = self.inner.send_request(request);
+        self.secret_prompt_pending = matches!(
+            response,
+            Ok(Response::AuthMessage { auth_message_type: AuthMessageType::Secret, .. })
+        );
+
+        match response {
+            Ok(response) => {
+                self.record(Event::Response(serde_json::to_value(&response)?));
+                Ok(response)
+            }
+            Err(error) => {
+                self.record(Event::Error(error.to_string()));
+                Err(error)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::greetd::scenario::Scenario;
+    use crate::greetd::transport::MockTransport;
+
+    static SCENARIO: &str = r#"
+        [default]
+        steps = [
+            { kind = "prompt", type = "visible", message = "OTP: " },
+            { kind = "prompt", type = "secret", message = "Password: ", expect = "hunter2" },
+        ]
+    "#;
+
+    #[test]
+    fn redacts_answers_to_secret_prompts() {
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let scenario: Scenario = toml::from_str(SCENARIO).unwrap();
+        let inner = MockTransport::with_scenario(scenario);
+        let mut transport = RecordingTransport::with_log(inner, log.reopen().unwrap());
+
+        transport.create_session("alice".to_owned()).unwrap();
+        transport.post_auth_message_response(Some("123456".to_owned())).unwrap();
+        let response = transport.post_auth_message_response(Some("hunter2".to_owned())).unwrap();
+        assert!(matches!(response, Response::Success));
+
+        let recording = std::fs::read_to_string(log.path()).unwrap();
+        assert!(!recording.contains("hunter2"));
+        assert!(recording.contains(REDACTED));
+        // Answers to visible prompts are kept, as they're needed to debug a conversation
+        assert!(recording.contains("123456"));
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use color_eyre::eyre::{bail, eyre, Context, OptionExt, Result};
+use greetd_ipc::{Request, Response};
+
+use super::recording::{Entry, Event};
+use super::Transport;
+use crate::args::get_args;
+
+/// How many transports have been created so far, i.e. which connection in the
+/// recording the next transport should play back.
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(0);
+
+/// A recorded request, and what came of it.
+#[derive(Debug)]
+struct Exchange {
+    /// The kind of request, e.g. `create_session`. Only the kind is checked
+    /// when replaying, since the recorded answers may have been redacted.
+    request: String,
+    response: Result<Response, String>,
+}
+
+/// Plays back a recording made with `--record`, answering each request with
+/// the next recorded response, as long as it's the same kind of request as
+/// the one recorded.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    exchanges: VecDeque<Exchange>,
+}
+
+fn request_kind(request: &serde_json::Value) -> String {
+    request.get("type").and_then(|kind| kind.as_str()).unwrap_or("unknown").to_owned()
+}
+
+/// Splits a recording into the exchanges of each connection in it.
+fn parse_recording(reader: impl Read) -> Result<Vec<VecDeque<Exchange>>> {
+    let mut connections = Vec::new();
+    // The last request, until its response comes up
+    let mut request = None;
+
+    for (number, line) in BufReader::new(reader).lines().enumerate() {
+        let entry: Entry = serde_json::from_str(&line?)
+            .wrap_err_with(|| format!("failed to parse line {} of recording", number + 1))?;
+
+        let response = match entry.event {
+            Event::Connected => {
+                connections.push(VecDeque::new());
+                request = None;
+                continue;
+            }
+            Event::Request(recorded) => {
+                request = Some(request_kind(&recorded));
+                continue;
+            }
+            Event::Response(response) => Ok(serde_json::from_value(response)?),
+            Event::Error(error) => Err(error),
+        };
+
+        let request = request
+            .take()
+            .ok_or_else(|| eyre!("line {} of recording answers no request", number + 1))?;
+        connections
+            .last_mut()
+            .ok_or_eyre("recording doesn't start with a connection")?
+            .push_back(Exchange { request, response });
+    }
+
+    Ok(connections)
+}
+
+impl Transport for ReplayTransport {
+    fn new() -> Result<Self> {
+        let path = get_args().replay.as_ref().ok_or_eyre("no recording given to replay")?;
+        let file = std::fs::File::open(path)
+            .wrap_err_with(|| format!("failed to open recording {}", path.display()))?;
+        let connections = parse_recording(file)?;
+
+        let index = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+        match connections.into_iter().nth(index) {
+            Some(exchanges) => Ok(Self { exchanges }),
+            None => bail!("recording has no connection #{}", index + 1),
+        }
+    }
+
+    fn send_request(&mut self, request: &Request) -> Result<Response> {
+        let Some(exchange) = self.exchanges.pop_front() else {
+            bail!("recording ended");
+        };
+
+        let kind = request_kind(&serde_json::to_value(request)?);
+        if kind != exchange.request {
+            bail!("expected a {} request as recorded, got {}", exchange.request, kind);
+        }
+
+        exchange.response.map_err(|error| eyre!(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::greetd::transport::{MockTransport, RecordingTransport};
+
+    fn record_conversation() -> tempfile::NamedTempFile {
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let inner = MockTransport::with_scenario(Default::default());
+        let mut transport = RecordingTransport::with_log(inner, log.reopen().unwrap());
+
+        transport.create_session("alice".to_owned()).unwrap();
+        transport.post_auth_message_response(Some("hunter2".to_owned())).unwrap();
+        log
+    }
+
+    fn replay(log: &tempfile::NamedTempFile) -> ReplayTransport {
+        let mut connections = parse_recording(log.reopen().unwrap()).unwrap();
+        assert_eq!(connections.len(), 1);
+        ReplayTransport { exchanges: connections.remove(0) }
+    }
+
+    #[test]
+    fn replays_recorded_responses() {
+        let log = record_conversation();
+        let mut transport = replay(&log);
+
+        let response = transport.create_session("alice".to_owned()).unwrap();
+        assert!(
+            matches!(response, Response::AuthMessage { auth_message, .. } if auth_message == "Password: ")
+        );
+        let response = transport.post_auth_message_response(Some("anything".to_owned())).unwrap();
+        assert!(matches!(response, Response::AuthMessage { .. }));
+
+        let error = transport.cancel_session().unwrap_err();
+        assert_eq!(error.to_string(), "recording ended");
+    }
+
+    #[test]
+    fn rejects_request_that_differs_from_recording() {
+        let log = record_conversation();
+        let mut transport = replay(&log);
+
+        let error = transport.cancel_session().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected a create_session request as recorded, got cancel_session"
+        );
+    }
+}
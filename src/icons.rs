@@ -0,0 +1,225 @@
+//! Looks up icons by name, following the [Icon Theme
+//! Specification](https://specifications.freedesktop.org/icon-theme-spec/latest/)
+//! closely enough for session icons.
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::desktop_entry::DesktopEntry;
+use crate::sessions::xdg_data_dirs;
+
+/// The size icons are shown at in the session picker.
+const ICON_SIZE: u32 = 48;
+
+/// Only formats the webview can show. XPM is allowed by the spec, but isn't
+/// one of them.
+static EXTENSIONS: &[&str] = &["svg", "png"];
+
+static FALLBACK_THEME: &str = "hicolor";
+static PIXMAPS_DIR: &str = "/usr/share/pixmaps";
+
+/// A directory of icons within a theme, e.g. `48x48/apps`.
+struct ThemeDir {
+    path: String,
+    /// How far the icons in this directory are from [`ICON_SIZE`].
+    distance: u32,
+}
+
+struct Theme {
+    /// Sorted from closest to furthest from [`ICON_SIZE`].
+    dirs: Vec<ThemeDir>,
+    inherits: Vec<String>,
+}
+
+/// Unlike in desktop entries, lists in theme indexes are separated by commas.
+fn comma_separated(value: Option<String>) -> Vec<String> {
+    let value = value.unwrap_or_default();
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(str::to_owned).collect()
+}
+
+impl Theme {
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Self> {
+        let entry = base_dirs
+            .iter()
+            .find_map(|dir| DesktopEntry::load(&dir.join(name).join("index.theme")).ok())?;
+        let group = entry.group("Icon Theme")?;
+
+        let mut dirs: Vec<_> = comma_separated(group.string("Directories"))
+            .into_iter()
+            .filter_map(|path| {
+                let dir = entry.group(&path)?;
+                let size = dir.string("Size")?.parse().ok()?;
+                let distance = size_distance(size, |key| dir.string(key));
+                Some(ThemeDir { path, distance })
+            })
+            .collect();
+        dirs.sort_by_key(|dir| dir.distance);
+
+        Some(Self { dirs, inherits: comma_separated(group.string("Inherits")) })
+    }
+}
+
+/// How far a theme directory's icons are from [`ICON_SIZE`], given its
+/// `Size=` and a way to look up its other keys.
+fn size_distance(size: u32, get: impl Fn(&str) -> Option<String>) -> u32 {
+    let get_number =
+        |key: &str, default: u32| get(key).and_then(|value| value.parse().ok()).unwrap_or(default);
+
+    let (min_size, max_size) = match get("Type").as_deref() {
+        Some("Fixed") => (size, size),
+        Some("Scalable") => (get_number("MinSize", size), get_number("MaxSize", size)),
+        _ => {
+            let threshold = get_number("Threshold", 2);
+            (size.saturating_sub(threshold), size + threshold)
+        }
+    };
+
+    if ICON_SIZE < min_size {
+        min_size - ICON_SIZE
+    } else {
+        ICON_SIZE.saturating_sub(max_size)
+    }
+}
+
+fn find_file(dirs: impl IntoIterator<Item = PathBuf>, name: &str) -> Option<PathBuf> {
+    dirs.into_iter().find_map(|dir| {
+        EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Resolves the value of an `Icon=` key, which is either an absolute path or
+/// the name of an icon to look up in `theme` (usually the one given by
+/// `--icon-theme`), the themes it inherits from, and finally the fallback
+/// theme.
+pub fn find_icon(icon: &str, theme: Option<&str>) -> Option<PathBuf> {
+    find_icon_in(icon, theme, &xdg_data_dirs())
+}
+
+/// Like [`find_icon`], but looks for themes in `data_dirs` instead of the XDG
+/// data directories.
+fn find_icon_in(icon: &str, theme: Option<&str>, data_dirs: &[PathBuf]) -> Option<PathBuf> {
+    let path = Path::new(icon);
+    if path.is_absolute() {
+        return path.is_file().then(|| path.to_owned());
+    }
+    // Anything else that looks like a path would escape the theme directories
+    if icon.is_empty() || icon.contains('/') {
+        return None;
+    }
+
+    let base_dirs: Vec<_> = data_dirs.iter().map(|dir| dir.join("icons")).collect();
+    let mut themes: VecDeque<String> = theme.into_iter().map(str::to_owned).collect();
+    themes.push_back(FALLBACK_THEME.to_owned());
+    let mut visited = HashSet::new();
+
+    while let Some(name) = themes.pop_front() {
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        let Some(theme) = Theme::load(&name, &base_dirs) else {
+            continue;
+        };
+
+        let dirs = theme
+            .dirs
+            .iter()
+            .flat_map(|dir| base_dirs.iter().map(|base_dir| base_dir.join(&name).join(&dir.path)));
+        if let Some(path) = find_file(dirs, icon) {
+            return Some(path);
+        }
+
+        // Inherited themes are searched next, before the fallback theme
+        for inherited in theme.inherits.into_iter().rev() {
+            themes.push_front(inherited);
+        }
+    }
+
+    find_file(base_dirs.into_iter().chain([PathBuf::from(PIXMAPS_DIR)]), icon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys<'a>(entries: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| entries.iter().find(|(name, _)| *name == key).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn measures_distance_to_icon_size() {
+        assert_eq!(size_distance(48, keys(&[("Type", "Fixed")])), 0);
+        assert_eq!(size_distance(32, keys(&[("Type", "Fixed")])), 16);
+        assert_eq!(size_distance(64, keys(&[("Type", "Fixed")])), 16);
+
+        let scalable = [("Type", "Scalable"), ("MinSize", "16"), ("MaxSize", "256")];
+        assert_eq!(size_distance(128, keys(&scalable)), 0);
+        assert_eq!(size_distance(16, keys(&[("Type", "Scalable")])), 32);
+
+        // Threshold directories are the default, with a threshold of 2
+        assert_eq!(size_distance(46, keys(&[])), 0);
+        assert_eq!(size_distance(44, keys(&[])), 2);
+        assert_eq!(size_distance(40, keys(&[("Type", "Threshold"), ("Threshold", "8")])), 0);
+    }
+
+    /// Writes a theme called `name` to `data_dir`, with a single 48x48
+    /// directory holding `icons`.
+    fn write_theme(data_dir: &Path, name: &str, inherits: &str, icons: &[&str]) {
+        let theme_dir = data_dir.join("icons").join(name);
+        std::fs::create_dir_all(theme_dir.join("48x48/apps")).unwrap();
+        std::fs::write(
+            theme_dir.join("index.theme"),
+            format!(
+                "[Icon Theme]\nName={}\nInherits={}\nDirectories=48x48/apps\n\n[48x48/apps]\nSize=48\nType=Fixed\n",
+                name, inherits
+            ),
+        )
+        .unwrap();
+        for icon in icons {
+            std::fs::write(theme_dir.join("48x48/apps").join(icon), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn searches_inherited_themes_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dirs = [dir.path().to_owned()];
+        write_theme(dir.path(), "breeze", "first, second", &["own.svg"]);
+        write_theme(dir.path(), "first", "", &["shared.png"]);
+        write_theme(dir.path(), "second", "", &["shared.svg", "second.svg"]);
+        write_theme(dir.path(), FALLBACK_THEME, "", &["second.png", "fallback.png"]);
+
+        let find = |icon| find_icon_in(icon, Some("breeze"), &data_dirs);
+        let theme_dir = |name: &str| dir.path().join("icons").join(name).join("48x48/apps");
+        assert_eq!(find("own"), Some(theme_dir("breeze").join("own.svg")));
+        assert_eq!(find("shared"), Some(theme_dir("first").join("shared.png")));
+        assert_eq!(find("second"), Some(theme_dir("second").join("second.svg")));
+        assert_eq!(find("fallback"), Some(theme_dir(FALLBACK_THEME).join("fallback.png")));
+        assert_eq!(find("missing"), None);
+    }
+
+    #[test]
+    fn accepts_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("logo.svg");
+        std::fs::write(&path, "").unwrap();
+
+        assert_eq!(find_icon_in(path.to_str().unwrap(), None, &[]), Some(path.clone()));
+        assert_eq!(find_icon_in(dir.path().join("missing.svg").to_str().unwrap(), None, &[]), None);
+    }
+
+    #[test]
+    fn rejects_names_that_are_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_dirs = [dir.path().to_owned()];
+        write_theme(dir.path(), FALLBACK_THEME, "", &["logo.svg"]);
+        std::fs::write(dir.path().join("icons/escaped.svg"), "").unwrap();
+
+        assert!(find_icon_in("logo", None, &data_dirs).is_some());
+        assert_eq!(find_icon_in("../../escaped", None, &data_dirs), None);
+        assert_eq!(find_icon_in("48x48/apps/logo", None, &data_dirs), None);
+        assert_eq!(find_icon_in("", None, &data_dirs), None);
+    }
+}
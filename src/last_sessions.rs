@@ -0,0 +1,99 @@
+//! Remembers the session each user last started, so that it can be selected
+//! for them next time.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+
+fn path(state_dir: &Path) -> PathBuf {
+    state_dir.join("last-sessions.json")
+}
+
+/// Maps usernames to the slugs of the sessions they last started.
+fn load(state_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let path = path(state_dir);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(error) => {
+            return Err(error).wrap_err_with(|| format!("failed to read {}", path.display()))
+        }
+    };
+    serde_json::from_str(&contents).wrap_err_with(|| format!("failed to parse {}", path.display()))
+}
+
+/// The slug of the session `username` last started, if any, as recorded in
+/// `state_dir`.
+pub fn get(state_dir: &Path, username: &str) -> Option<String> {
+    match load(state_dir) {
+        Ok(mut sessions) => sessions.remove(username),
+        Err(error) => {
+            eprintln!("Failed to load last sessions: {:?}", error);
+            None
+        }
+    }
+}
+
+/// Records in `state_dir` that `username` started the session `slug`.
+pub fn set(state_dir: &Path, username: &str, slug: &str) -> Result<()> {
+    let mut sessions = load(state_dir)?;
+    sessions.insert(username.to_owned(), slug.to_owned());
+
+    let path = path(state_dir);
+    let dir = path.parent().unwrap();
+    std::fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+
+    // Write to a temporary file first so that a crash can't leave a truncated
+    // file behind
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, serde_json::to_string_pretty(&sessions)?)
+        .wrap_err_with(|| format!("failed to write {}", temp_path.display()))?;
+    std::fs::rename(&temp_path, &path)
+        .wrap_err_with(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_last_session_per_user() {
+        let dir = tempfile::tempdir().unwrap();
+        // The state directory is created when it's first written to
+        let state_dir = dir.path().join("state");
+
+        assert_eq!(get(&state_dir, "alice"), None);
+
+        set(&state_dir, "alice", "sway").unwrap();
+        set(&state_dir, "bob", "plasma").unwrap();
+        set(&state_dir, "alice", "gnome").unwrap();
+
+        assert_eq!(get(&state_dir, "alice").as_deref(), Some("gnome"));
+        assert_eq!(get(&state_dir, "bob").as_deref(), Some("plasma"));
+        assert_eq!(get(&state_dir, "carol"), None);
+    }
+
+    #[test]
+    fn replaces_file_instead_of_writing_to_it() {
+        let dir = tempfile::tempdir().unwrap();
+        set(dir.path(), "alice", "sway").unwrap();
+        // Anyone still reading the old file keeps seeing its old contents
+        let old_file = std::fs::File::open(path(dir.path())).unwrap();
+
+        set(dir.path(), "alice", "gnome").unwrap();
+
+        let old: BTreeMap<String, String> = serde_json::from_reader(old_file).unwrap();
+        assert_eq!(old["alice"], "sway");
+        assert!(!dir.path().join("last-sessions.json.tmp").exists());
+        assert_eq!(get(dir.path(), "alice").as_deref(), Some("gnome"));
+    }
+
+    #[test]
+    fn ignores_unreadable_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(path(dir.path()), "not json").unwrap();
+
+        assert_eq!(get(dir.path(), "alice"), None);
+    }
+}
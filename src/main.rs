@@ -1,27 +1,66 @@
 mod args;
 mod components;
+mod config;
+mod desktop_entry;
+mod doctor;
 mod greetd;
+mod icons;
+mod last_sessions;
 mod sessions;
+mod watch;
+mod wrapper;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::Duration;
 
-use args::get_args;
+use args::{get_args, Command};
 use color_eyre::eyre::{bail, Result};
-use components::{Button, Input, SessionSelector};
+use components::{Button, Input, SecondaryButton, SessionPicker};
+use config::get_config;
 use dioxus::desktop::{Config, WindowBuilder};
 use dioxus::prelude::*;
+use greetd::error::Error as GreetdError;
 use greetd::session_builder::{
-    self, AnsweredQuestion, NeedAuthResponse, SessionBuilder, SessionCreated,
+    self, AnsweredQuestion, Message, NeedAuthResponse, SessionBuilder, SessionCreated,
+};
+use greetd::transport::{
+    GreetdTransport, MockTransport, RecordingTransport, ReplayTransport, Transport,
 };
-use greetd::transport::{GreetdTransport, MockTransport, Transport};
 use greetd_ipc::AuthMessageType;
-use sessions::Session;
+use sessions::{OtherDisplayManagers, Session, UserSettings};
+use zeroize::Zeroize;
 
 const TAILWIND_CSS: Asset = asset!("/assets/tailwind.css");
 
-fn main() {
-    let demo = get_args().demo;
+/// How long to wait between attempts to create the session while greetd is
+/// unavailable, on top of the backoff within each attempt.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+fn main() -> Result<()> {
+    match &get_args().command {
+        // Loads the config itself, so that it can report what's wrong with it
+        Some(Command::Doctor) => std::process::exit(if doctor::run() { 0 } else { 1 }),
+        // Doesn't use the config, so a broken one shouldn't stop it from starting
+        Some(Command::FakeGreetd { socket, scenario }) => {
+            return greetd::fake_server::run(socket, scenario.as_deref());
+        }
+        Some(Command::Sessions { verbose }) => {
+            config::init(config::load()?);
+            let config = get_config();
+            sessions::print_sessions(
+                &custom_sessions(),
+                &config.session_dirs,
+                config.icon_theme.as_deref(),
+                *verbose,
+            );
+            return Ok(());
+        }
+        None => config::init(config::load()?),
+    }
+
+    let args = get_args();
+    let demo = args.demo.is_some();
 
     let config = dioxus::LaunchBuilder::new().with_cfg(
         Config::default().with_menu(None).with_window(
@@ -32,92 +71,398 @@ fn main() {
         ),
     );
 
-    if demo {
-        config.launch(App::<MockTransport>)
+    match (demo, &args.replay) {
+        (true, Some(_)) => launch::<ReplayTransport>(config),
+        (true, None) => launch::<MockTransport>(config),
+        (false, _) => launch::<GreetdTransport>(config),
+    }
+
+    Ok(())
+}
+
+fn launch<T: Transport + Send + 'static>(config: LaunchBuilder) {
+    if get_args().record.is_some() {
+        config.launch(App::<RecordingTransport<T>>)
     } else {
-        config.launch(App::<GreetdTransport>)
+        config.launch(App::<T>)
     }
 }
 
+fn custom_sessions() -> Vec<Session> {
+    let config = get_config();
+    config.sessions.iter().map(|session| session.to_session(config.icon_theme.as_deref())).collect()
+}
+
 fn get_sessions() -> Vec<Session> {
-    if get_args().demo {
+    if get_args().demo.is_some() {
         sessions::get_sessions_mock()
     } else {
-        sessions::get_sessions()
+        let config = get_config();
+        sessions::get_sessions(
+            &custom_sessions(),
+            &config.session_dirs,
+            config.icon_theme.as_deref(),
+        )
     }
 }
 
+fn find_session(sessions: &[Session], slug: Option<&str>) -> Option<Session> {
+    slug.and_then(|slug| sessions.iter().find(|session| session.slug == slug).cloned())
+}
+
 struct State<T: Transport> {
     session_builder: Option<SessionBuilder<T>>,
     value: String,
     sessions: Vec<Session>,
     session: Option<Session>,
+    /// Set once the user picks a session themselves, after which it's no
+    /// longer replaced by the one they last started.
+    session_picked: bool,
+    /// The username the current conversation with greetd was started for.
+    username: String,
+    authenticating: bool,
+    /// Set when greetd couldn't be reached, in which case creating the session
+    /// is retried in the background until it can.
+    unavailable: bool,
 }
 
 impl<T: Transport> Default for State<T> {
     fn default() -> Self {
         let sessions = get_sessions();
+        let session = find_session(&sessions, get_config().default_session.as_deref());
+
+        Self {
+            session_builder: Default::default(),
+            value: Default::default(),
+            sessions,
+            session,
+            session_picked: false,
+            username: Default::default(),
+            authenticating: false,
+            unavailable: false,
+        }
+    }
+}
+
+/// A single request to greetd, detached from [`State`] so that it can be run
+/// on a worker thread. Talking to greetd blocks for as long as PAM takes to
+/// answer, which would otherwise freeze the whole webview.
+enum Step<T: Transport> {
+    CreateSession {
+        username: String,
+    },
+    PostAuthMessageResponse {
+        builder: NeedAuthResponse<T>,
+        value: String,
+        username: String,
+        session: Option<Session>,
+    },
+    StartSession {
+        builder: SessionCreated<T>,
+        username: String,
+        session: Session,
+    },
+}
 
-        let session = get_args()
-            .default_session
-            .as_deref()
-            .and_then(|slug| sessions.iter().find(|session| session.slug == slug).cloned());
+/// What came of a [`Step`]. Failed authentication is retried right away, in
+/// which case the fresh conversation comes with the error that caused it.
+struct Outcome<T: Transport> {
+    builder: SessionBuilder<T>,
+    retried_after: Option<GreetdError>,
+    /// The slugs of the sessions to select for the user, best first, once a
+    /// conversation has been started for them.
+    preferred_sessions: Option<Vec<String>>,
+}
 
-        Self { session_builder: Default::default(), value: Default::default(), sessions, session }
+impl<T: Transport> From<SessionBuilder<T>> for Outcome<T> {
+    fn from(builder: SessionBuilder<T>) -> Self {
+        Self { builder, retried_after: None, preferred_sessions: None }
+    }
+}
+
+/// The slugs of the sessions to select for `username`, best first: what they
+/// last started with this greeter, then with another display manager, then
+/// the site default. Looking these up can block on NSS and the user's home
+/// directory, so it's done on the worker thread.
+fn preferred_session_slugs(username: &str) -> Vec<String> {
+    let config = get_config();
+    [
+        last_sessions::get(&config.state_dir, username),
+        OtherDisplayManagers::system().preferred_session(username),
+        config.default_session.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+impl<T: Transport> Step<T> {
+    /// Sends the request to greetd, blocking until it has been answered.
+    fn run(self) -> greetd::error::Result<Outcome<T>> {
+        match self {
+            Self::CreateSession { username } => {
+                let builder = session_builder::create_session(username.clone())?;
+                let preferred_sessions = Some(preferred_session_slugs(&username));
+                Ok(Outcome { builder, retried_after: None, preferred_sessions })
+            }
+
+            Self::PostAuthMessageResponse { builder, value, username, session } => {
+                let builder = match builder.post_auth_message_response(Some(value)) {
+                    // The builder cancels the session with greetd when authentication fails, so
+                    // start a new one for the same username rather than making the user retype it
+                    Err(error @ GreetdError::Auth(_)) => {
+                        let builder = session_builder::create_session(username)?;
+                        return Ok(Outcome {
+                            builder,
+                            retried_after: Some(error),
+                            preferred_sessions: None,
+                        });
+                    }
+                    result => result?,
+                };
+
+                match (builder, session) {
+                    // If this auth response led to the session being created, automatically try to start it
+                    (SessionBuilder::SessionCreated(builder), Some(session)) => {
+                        Self::StartSession { builder, username, session }.run()
+                    }
+                    (builder, _) => Ok(builder.into()),
+                }
+            }
+
+            Self::StartSession { builder, username, session } => {
+                // The config may be reloaded at any moment, so stick to one
+                let config = get_config();
+                let display = session.x_display(&username);
+                let cmd = session.to_command(display.as_ref(), &config.x_launcher);
+                let shell = wrapper::login_shell(&username);
+                let cmd = config.wrapper_for(&session.slug).wrap(cmd, &session.slug, &shell);
+                eprintln!("Starting session {} with {:?}", session.slug, cmd);
+                let user = UserSettings::for_user(&OtherDisplayManagers::system(), &username);
+                let env = session.to_environment(
+                    display.as_ref(),
+                    &sessions::greeter_environment(),
+                    &config.site_environment(),
+                    &user,
+                );
+                builder.start_session(cmd, env)?;
+                // Demo logins aren't real, so they shouldn't change what real users get offered
+                if get_args().demo.is_none() {
+                    if let Err(error) =
+                        last_sessions::set(&config.state_dir, &username, &session.slug)
+                    {
+                        eprintln!("Failed to remember last session: {:?}", error);
+                    }
+                }
+                std::process::exit(0);
+            }
+        }
     }
 }
 
 impl<T: Transport> State<T> {
-    fn submit(&mut self) -> Result<()> {
-        Ok(match std::mem::take(&mut self.session_builder) {
+    /// Takes the current input out of the state and works out what to send to
+    /// greetd next. The state is marked as authenticating until the outcome is
+    /// passed to [`State::finish_submit`].
+    fn begin_submit(&mut self) -> Result<Step<T>> {
+        let step = match self.session_builder.take() {
             None => {
-                let value = std::mem::take(&mut self.value);
-                self.session_builder = Some(session_builder::create_session(value)?);
+                self.username = std::mem::take(&mut self.value);
+                Step::CreateSession { username: self.username.clone() }
             }
 
-            Some(SessionBuilder::NeedAuthResponse(builder)) => {
-                let value = std::mem::take(&mut self.value);
-                self.session_builder = Some(builder.post_auth_message_response(Some(value))?);
+            Some(SessionBuilder::NeedAuthResponse(builder)) => Step::PostAuthMessageResponse {
+                builder,
+                value: std::mem::take(&mut self.value),
+                username: self.username.clone(),
+                session: self.session.clone(),
+            },
 
-                // If this auth response led to the session being created, automatically try to start it
-                if let Some(SessionBuilder::SessionCreated(_)) = self.session_builder {
-                    return self.submit();
+            Some(SessionBuilder::SessionCreated(builder)) => match self.session.clone() {
+                Some(session) => {
+                    Step::StartSession { builder, username: self.username.clone(), session }
                 }
+                None => {
+                    self.session_builder = Some(SessionBuilder::SessionCreated(builder));
+                    bail!("No session selected");
+                }
+            },
+        };
+
+        self.authenticating = true;
+        Ok(step)
+    }
+
+    /// Tries creating the session again after greetd was unavailable, unless
+    /// the user has given up in the meantime.
+    fn begin_reconnect(&mut self) -> Option<Step<T>> {
+        if !self.unavailable || self.authenticating {
+            return None;
+        }
+
+        self.authenticating = true;
+        Some(Step::CreateSession { username: self.username.clone() })
+    }
+
+    /// Replaces the contents of the active input, wiping the old contents as
+    /// they may be (part of) a secret.
+    fn set_value(&mut self, value: String) {
+        self.value.zeroize();
+        self.value = value;
+    }
+
+    /// Abandons the current conversation and goes back to asking for a
+    /// username. Returns the builder whose session still has to be cancelled.
+    fn cancel(&mut self) -> Option<SessionBuilder<T>> {
+        self.value.zeroize();
+        self.username.clear();
+        self.session_picked = false;
+        self.unavailable = false;
+        self.session_builder.take()
+    }
+
+    /// Replaces the sessions after they changed on disk, keeping the selected
+    /// one if it's still there. The conversation with greetd is left alone.
+    fn reload_sessions(&mut self, sessions: Vec<Session>) {
+        let selected = self.session.as_ref().map(|session| session.slug.clone());
+        // Until a username is entered, the selection is only the default, which may have changed
+        self.session = if self.username.is_empty() && !self.session_picked {
+            find_session(&sessions, get_config().default_session.as_deref())
+        } else {
+            find_session(&sessions, selected.as_deref())
+        };
+        self.sessions = sessions;
+    }
+
+    /// Stores the outcome of a [`Step`] once greetd has answered.
+    fn finish_submit(&mut self, result: greetd::error::Result<Outcome<T>>) -> Result<()> {
+        self.authenticating = false;
+        self.unavailable = false;
+
+        let Outcome { builder, retried_after, preferred_sessions } = match result {
+            Ok(outcome) => outcome,
+            Err(error @ GreetdError::Unavailable(_)) => {
+                eprintln!("Failed to connect: {}", error);
+                self.unavailable = true;
+                return Ok(());
             }
+            Err(error) => {
+                eprintln!("Failed to log in: {}", error);
 
-            Some(SessionBuilder::SessionCreated(builder)) => {
-                let session = match self.session.as_ref() {
-                    Some(session) => session,
-                    None => {
-                        self.session_builder = Some(SessionBuilder::SessionCreated(builder));
-                        bail!("No session selected");
-                    }
-                };
+                // Spare the user from retyping their username if it's worth another try
+                if error.keeps_username() {
+                    let username = std::mem::take(&mut self.username);
+                    self.set_value(username);
+                }
 
-                builder.start_session(session.exec.clone(), session.to_environment())?;
-                std::process::exit(0);
+                bail!(error.user_message());
             }
-        })
+        };
+
+        if let Some(slugs) = preferred_sessions.filter(|_| !self.session_picked) {
+            self.session = slugs.iter().find_map(|slug| find_session(&self.sessions, Some(slug)));
+        }
+
+        let builder = self.session_builder.insert(builder);
+
+        if let Some(error) = retried_after {
+            eprintln!("Failed to log in, retrying: {}", error);
+            bail!(error.user_message());
+        }
+
+        if let SessionBuilder::SessionCreated(_) = builder {
+            if self.session.is_none() {
+                bail!("No session selected");
+            }
+        }
+
+        Ok(())
     }
 }
 
 #[component]
-fn App<T: Transport + 'static>() -> Element {
+fn App<T: Transport + Send + 'static>() -> Element {
     let mut state = use_signal(|| State::<T>::default());
     let mut input_element: Signal<Option<Rc<MountedData>>> = use_signal(|| None);
     let mut error_message = use_signal(|| None);
 
-    let oninput_value = move |event: FormEvent| state.write().value = event.value();
-    let onchange_session = move |session: Option<Session>| state.write().session = session;
+    let oninput_value = move |event: FormEvent| state.write().set_value(event.value());
+    let onchange_session = move |session: Session| {
+        let mut state = state.write();
+        state.session = Some(session);
+        state.session_picked = true;
+    };
 
     let onsubmit = move |event: FormEvent| {
         event.prevent_default();
-        match state.write().submit() {
-            Ok(()) => error_message.set(None),
-            Err(error) => error_message.set(Some(error.to_string())),
+
+        // Refuse double submits while greetd is still busy with the previous one, or
+        // while it's being reconnected to in the background
+        if state.read().authenticating || state.read().unavailable {
+            return;
+        }
+
+        let mut step = match state.write().begin_submit() {
+            Ok(step) => step,
+            Err(error) => return error_message.set(Some(error.to_string())),
         };
+
+        spawn(async move {
+            loop {
+                let result = tokio::task::spawn_blocking(move || step.run())
+                    .await
+                    .unwrap_or_else(|error| Err(GreetdError::Transport(error.into())));
+
+                match state.write().finish_submit(result) {
+                    Ok(()) => error_message.set(None),
+                    Err(error) => error_message.set(Some(error.to_string())),
+                };
+
+                // Keep trying to reach greetd until it's back or the user gives up
+                if !state.read().unavailable {
+                    break;
+                }
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                match state.write().begin_reconnect() {
+                    Some(next_step) => step = next_step,
+                    None => break,
+                }
+            }
+        });
+    };
+
+    let mut cancel = move || {
+        // The builder is on the worker thread while authenticating
+        if state.read().authenticating {
+            return;
+        }
+
+        error_message.set(None);
+
+        if let Some(builder) = state.write().cancel() {
+            spawn(async move {
+                match tokio::task::spawn_blocking(move || builder.cancel()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => eprintln!("Failed to cancel session: {}", error),
+                    Err(error) => eprintln!("Failed to cancel session: {}", error),
+                }
+            });
+        }
     };
 
+    // Pick up changes to the config and session files while the greeter is running
+    use_future(move || async move {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        std::thread::spawn(move || {
+            if let Err(error) = watch::run(|| sender.blocking_send(get_sessions()).is_ok()) {
+                eprintln!("Stopped watching for changes: {:?}", error);
+            }
+        });
+        while let Some(sessions) = receiver.recv().await {
+            state.write().reload_sessions(sessions);
+        }
+    });
+
     use_effect(move || {
         if let Some(header) = input_element() {
             let _ = header.set_focus(true);
@@ -140,8 +485,8 @@ fn App<T: Transport + 'static>() -> Element {
         prev_answers
             .iter()
             .map(|value| match value {
-                AnsweredQuestion::Visible(value) => (value, false),
-                AnsweredQuestion::Secret(value) => (value, true),
+                AnsweredQuestion::Visible(value) => (value.clone(), false),
+                AnsweredQuestion::Secret(length) => ("*".repeat(*length), true),
             })
             .map(|(value, secure)| {
                 rsx!(Input {
@@ -160,6 +505,7 @@ fn App<T: Transport + 'static>() -> Element {
         // The currently active text input.
 
         let description_and_secure = match &state.read().session_builder {
+            None if state.read().unavailable => None,
             None => Some(("Username".to_owned(), false)),
             Some(SessionBuilder::NeedAuthResponse(NeedAuthResponse {
                 auth_message_type: AuthMessageType::Visible,
@@ -180,6 +526,7 @@ fn App<T: Transport + 'static>() -> Element {
                     placeholder: description.trim().trim_end_matches(":"),
                     value: &state.read().value,
                     secure: secure,
+                    disabled: state.read().authenticating,
                     onmounted: move |elem: MountedEvent| input_element.set(Some(elem.data())),
                     oninput: oninput_value
                 }
@@ -187,22 +534,39 @@ fn App<T: Transport + 'static>() -> Element {
         })
     };
 
-    let info_message = {
-        let message = match &state.read().session_builder {
-            Some(SessionBuilder::NeedAuthResponse(NeedAuthResponse {
-                auth_message_type: AuthMessageType::Info | AuthMessageType::Error,
-                auth_message,
-                ..
-            })) => Some(auth_message.clone()),
-            _ => None,
+    let unavailable_notice = state.read().unavailable.then(|| {
+        rsx!(p {
+            class: "text-center",
+            "greetd unavailable, retrying…"
+        })
+    });
+
+    let messages: Vec<_> = {
+        // Info and error messages from PAM, which were acknowledged automatically
+
+        let state_value = state.read();
+
+        let messages = match &state_value.session_builder {
+            None => &[][..],
+            Some(
+                SessionBuilder::NeedAuthResponse(NeedAuthResponse { messages, .. })
+                | SessionBuilder::SessionCreated(SessionCreated { messages, .. }),
+            ) => &messages[..],
         };
 
-        message.map(|message| {
-            rsx!(p {
-                class: "text-center",
-                {message}
+        messages
+            .iter()
+            .map(|message| match message {
+                Message::Info(message) => (message, "text-center"),
+                Message::Error(message) => (message, "text-center text-red-500"),
             })
-        })
+            .map(|(message, class)| {
+                rsx!(p {
+                    class: class,
+                    {message.clone()}
+                })
+            })
+            .collect()
     };
 
     let error_message = error_message().map(|message| {
@@ -212,16 +576,33 @@ fn App<T: Transport + 'static>() -> Element {
         })
     });
 
+    let back_button =
+        (state.read().session_builder.is_some() || state.read().unavailable).then(|| {
+            rsx!(SecondaryButton {
+                disabled: state.read().authenticating,
+                onclick: move |_| cancel(),
+                "Back"
+            })
+        });
+
     rsx! {
         document::Link { rel: "stylesheet", href: TAILWIND_CSS }
         FormWrapper {
+            background: get_config().background.clone(),
+            logo: get_config().logo.clone(),
             onsubmit: onsubmit,
+            oncancel: move |()| cancel(),
             {answered_question_inputs.iter()}
             {next_input}
-            {info_message}
-            Button { "Submit" }
+            {unavailable_notice}
+            {messages.iter()}
+            Button {
+                disabled: state.read().authenticating || state.read().unavailable,
+                if state.read().authenticating { "Authenticating…" } else { "Submit" }
+            }
+            {back_button}
             {error_message}
-            SessionSelector {
+            SessionPicker {
                 sessions: state.read().sessions.clone(),
                 session: state.read().session.clone(),
                 onchange: onchange_session
@@ -232,7 +613,11 @@ fn App<T: Transport + 'static>() -> Element {
 
 #[derive(PartialEq, Props, Clone)]
 struct FormWrapperProps {
+    background: Option<PathBuf>,
+    logo: Option<PathBuf>,
     onsubmit: EventHandler<FormEvent>,
+    /// Called when Escape is pressed anywhere in the form.
+    oncancel: EventHandler,
     children: Element,
 }
 
@@ -245,12 +630,17 @@ pub fn FormWrapper(props: FormWrapperProps) -> Element {
     rsx! {
         div {
             class: "h-full bg-center bg-cover flex flex-col items-center justify-center gap-4",
-            background_image: maybe_css_url(get_args().background.as_deref()),
-            if let Some(src) = maybe_css_url(get_args().logo.as_deref()) {
+            background_image: maybe_css_url(props.background.as_deref()),
+            if let Some(src) = maybe_css_url(props.logo.as_deref()) {
                 img { src: src, class: "w-20" }
             }
             form {
                 onsubmit: props.onsubmit,
+                onkeydown: move |event: KeyboardEvent| {
+                    if event.key() == Key::Escape {
+                        props.oncancel.call(());
+                    }
+                },
                 class: "p-4 w-96 flex flex-col gap-4 rounded-lg",
                 {props.children}
             }
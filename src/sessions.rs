@@ -1,24 +1,35 @@
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use color_eyre::eyre::{OptionExt, Result};
-use ini::Ini;
+use color_eyre::eyre::{bail, OptionExt, Report, Result};
 use itertools::Itertools;
+use nix::unistd::User;
+use serde::Deserialize;
+
+use crate::args::{Argv, SessionDir};
+use crate::desktop_entry::{DesktopEntry, Locale};
+use crate::icons::find_icon;
 
 static DEFAULT_XDG_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+static DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+static ACCOUNTS_SERVICE_USERS_DIR: &str = "/var/lib/AccountsService/users";
 
-static SESSION_SUBDIRS: &[(&str, SessionType)] = &[
-    // FIXME: X sessions don't launch correctly
-    // Need to launch X server before launching the session
-    // ("xsessions", SessionType::X11),
-    ("wayland-sessions", SessionType::Wayland),
-];
+/// Wayland sessions come first, so that where a desktop installs a session
+/// file with the same name in both (like GNOME), the Wayland one is offered.
+static SESSION_SUBDIRS: &[(&str, SessionType)] =
+    &[("wayland-sessions", SessionType::Wayland), ("xsessions", SessionType::X11)];
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SessionType {
-    #[allow(dead_code)]
     X11,
     Wayland,
+    /// A text-mode session, e.g. a shell, started directly on the console.
+    Tty,
 }
 
 impl Display for SessionType {
@@ -26,6 +37,34 @@ impl Display for SessionType {
         match self {
             Self::X11 => write!(f, "x11"),
             Self::Wayland => write!(f, "wayland"),
+            Self::Tty => write!(f, "tty"),
+        }
+    }
+}
+
+impl SessionType {
+    /// All session types, in the order they're shown in.
+    pub const ALL: [Self; 3] = [Self::Wayland, Self::X11, Self::Tty];
+
+    /// A human-readable name for the session type.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::X11 => "X11",
+            Self::Wayland => "Wayland",
+            Self::Tty => "Console",
+        }
+    }
+}
+
+impl FromStr for SessionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x11" => Ok(Self::X11),
+            "wayland" => Ok(Self::Wayland),
+            "tty" => Ok(Self::Tty),
+            _ => Err(format!("unknown session type {:?}", s)),
         }
     }
 }
@@ -34,9 +73,20 @@ impl Display for SessionType {
 pub struct Session {
     pub slug: String,
     pub name: String,
+    /// A longer description of the session, e.g. for a tooltip.
+    pub comment: Option<String>,
     pub exec: Vec<String>,
     pub r#type: SessionType,
     pub desktop_names: Vec<String>,
+    /// The session's icon, if it has one that could be found.
+    pub icon: Option<PathBuf>,
+    /// Extra environment variables to start the session with.
+    pub env: Vec<(String, String)>,
+    /// Set by `Hidden=true` or `NoDisplay=true`. Hidden sessions still shadow
+    /// sessions with the same slug in lower-precedence directories.
+    pub hidden: bool,
+    /// A program that must be installed for the session to be offered.
+    pub try_exec: Option<String>,
 }
 
 impl Display for Session {
@@ -45,13 +95,162 @@ impl Display for Session {
     }
 }
 
+/// The X display an X11 session's server is started on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XDisplay {
+    pub number: u32,
+    /// Where the X launcher should put the authority file, if the user's home
+    /// directory could be found.
+    pub xauthority: Option<PathBuf>,
+}
+
+impl XDisplay {
+    /// Picks the lowest display number that no X server is running on.
+    pub fn for_user(username: &str) -> Self {
+        let in_use = |number: &u32| {
+            Path::new(&format!("/tmp/.X{}-lock", number)).exists()
+                || Path::new(&format!("/tmp/.X11-unix/X{}", number)).exists()
+        };
+        let number = (0..).find(|number| !in_use(number)).unwrap();
+
+        let xauthority = match User::from_name(username) {
+            Ok(Some(user)) => Some(user.dir.join(".Xauthority")),
+            _ => None,
+        };
+
+        Self { number, xauthority }
+    }
+}
+
+/// Looks up a program the way `execvp` would, returning its path if it exists
+/// and is executable.
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    };
+
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+
+    let path = std::env::var("PATH").unwrap_or_else(|_| DEFAULT_PATH.to_owned());
+    path.split(":").map(|dir| Path::new(dir).join(program)).find(|path| is_executable(path))
+}
+
 impl Session {
-    pub fn to_environment(&self) -> Vec<String> {
-        vec![
-            format!("XDG_SESSION_TYPE={}", self.r#type),
-            format!("XDG_SESSION_DESKTOP={}", self.slug),
-            format!("XDG_CURRENT_DESKTOP={}", self.desktop_names.join(":")),
-        ]
+    /// Checks whether the session should be offered to the user, i.e. that it
+    /// isn't hidden and that its programs are installed.
+    pub fn check_available(&self) -> Result<()> {
+        if self.hidden {
+            bail!("session is marked Hidden or NoDisplay");
+        }
+        if let Some(try_exec) = &self.try_exec {
+            if find_in_path(try_exec).is_none() {
+                bail!("TryExec= program {} not found", try_exec);
+            }
+        }
+        match self.exec.first() {
+            None => bail!("Exec= is empty"),
+            Some(program) if find_in_path(program).is_none() => {
+                bail!("Exec= program {} not found", program)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// X11 sessions need an X server started for them, which needs a display.
+    pub fn x_display(&self, username: &str) -> Option<XDisplay> {
+        (self.r#type == SessionType::X11).then(|| XDisplay::for_user(username))
+    }
+
+    /// The command to start the session with. Sessions with an X display are
+    /// wrapped in `x_launcher`, with `{exec}` replaced by the session's command
+    /// and `{display}` by the display number.
+    pub fn to_command(&self, display: Option<&XDisplay>, x_launcher: &Argv) -> Vec<String> {
+        let Some(display) = display else {
+            return self.exec.clone();
+        };
+
+        x_launcher
+            .0
+            .iter()
+            .flat_map(|arg| match arg.as_str() {
+                "{exec}" => self.exec.clone(),
+                arg => vec![arg.replace("{display}", &display.number.to_string())],
+            })
+            .collect()
+    }
+
+    /// The session's environment, as `KEY=VALUE` pairs sorted by key. Later
+    /// sources take precedence over earlier ones:
+    ///
+    /// 1. What the session inherits from the greeter, see
+    ///    [`greeter_environment`].
+    /// 2. The site-wide environment from the config, see
+    ///    [`Config::site_environment`](crate::config::Config::site_environment).
+    /// 3. The session's own environment, e.g. from `X-OCF-Environment=`.
+    /// 4. The user's language.
+    /// 5. The variables describing the session itself, such as
+    ///    `XDG_SESSION_TYPE` and `DISPLAY`, which can't be overridden.
+    pub fn to_environment(
+        &self,
+        display: Option<&XDisplay>,
+        inherited_environment: &BTreeMap<String, String>,
+        site_environment: &BTreeMap<String, String>,
+        user: &UserSettings,
+    ) -> Vec<String> {
+        let mut environment = inherited_environment.clone();
+        environment.extend(site_environment.clone());
+        environment.extend(self.env.iter().cloned());
+
+        if let Some(lang) = &user.lang {
+            environment.insert("LANG".to_owned(), lang.clone());
+        }
+
+        environment.extend([
+            ("XDG_SESSION_CLASS".to_owned(), "user".to_owned()),
+            ("XDG_SESSION_TYPE".to_owned(), self.r#type.to_string()),
+            ("XDG_SESSION_DESKTOP".to_owned(), self.slug.clone()),
+            ("XDG_CURRENT_DESKTOP".to_owned(), self.desktop_names.join(":")),
+        ]);
+
+        if let Some(display) = display {
+            environment.insert("DISPLAY".to_owned(), format!(":{}", display.number));
+            if let Some(xauthority) = &display.xauthority {
+                environment.insert("XAUTHORITY".to_owned(), xauthority.display().to_string());
+            }
+        }
+
+        environment.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect()
+    }
+}
+
+/// Per-user settings that end up in the session's environment. Only settings
+/// that were actually chosen for the user are set, so that they don't override
+/// the site's defaults with the greeter's.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserSettings {
+    pub lang: Option<String>,
+}
+
+/// The greeter's own `LANG` and `XKB_DEFAULT_LAYOUT`, which sessions get
+/// unless something else sets them.
+pub fn greeter_environment() -> BTreeMap<String, String> {
+    ["LANG", "XKB_DEFAULT_LAYOUT"]
+        .into_iter()
+        .filter_map(|key| {
+            let value = std::env::var(key).ok().filter(|value| !value.is_empty())?;
+            Some((key.to_owned(), value))
+        })
+        .collect()
+}
+
+impl UserSettings {
+    /// The language `username` picked in another display manager, if any.
+    pub fn for_user(display_managers: &OtherDisplayManagers, username: &str) -> Self {
+        Self { lang: display_managers.language(username) }
     }
 }
 
@@ -60,57 +259,722 @@ pub fn get_sessions_mock() -> Vec<Session> {
         Session {
             slug: "test-wayland".to_owned(),
             name: "Test (Wayland)".to_owned(),
+            comment: Some("A pretend Wayland session".to_owned()),
             exec: vec![],
             r#type: SessionType::Wayland,
             desktop_names: vec![],
+            icon: None,
+            env: vec![],
+            hidden: false,
+            try_exec: None,
         },
         Session {
             slug: "test-xorg".to_owned(),
             name: "Test (Xorg)".to_owned(),
+            comment: None,
             exec: vec![],
             r#type: SessionType::X11,
             desktop_names: vec![],
+            icon: None,
+            env: vec![],
+            hidden: false,
+            try_exec: None,
         },
     ]
 }
 
-pub fn get_sessions() -> Vec<Session> {
+/// Where a session was defined.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Config,
+    File(PathBuf),
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config => write!(f, "config file"),
+            Self::File(path) => path.display().fmt(f),
+        }
+    }
+}
+
+/// What became of a session found while looking for sessions.
+#[derive(Debug)]
+pub enum Status {
+    Offered(Session),
+    /// A session with the same slug from a higher-precedence source came
+    /// first.
+    Shadowed {
+        session: Session,
+        by: Source,
+    },
+    /// The session is fine, but can't be offered, e.g. because it's hidden or
+    /// its programs aren't installed.
+    Unavailable {
+        session: Session,
+        reason: Report,
+    },
+    /// The file couldn't be read or isn't a valid session file.
+    Invalid(Report),
+}
+
+#[derive(Debug)]
+pub struct Candidate {
+    pub source: Source,
+    pub r#type: SessionType,
+    pub status: Status,
+}
+
+pub fn xdg_data_dirs() -> Vec<PathBuf> {
     let xdg_data_dirs =
         std::env::var("XDG_DATA_DIRS").unwrap_or_else(|_| DEFAULT_XDG_DATA_DIRS.to_owned());
+    xdg_data_dirs.split(":").map(PathBuf::from).collect()
+}
 
-    let session_dirs = xdg_data_dirs.split(":").flat_map(|dir| {
-        let dir = PathBuf::from(dir);
-        SESSION_SUBDIRS.iter().map(move |(subdir, r#type)| (dir.join(subdir), *r#type))
+/// The directories to look for session files in, from highest to lowest
+/// precedence: `extra_dirs` in order, then those in the XDG data directories.
+pub fn session_dirs(extra_dirs: &[SessionDir]) -> Vec<SessionDir> {
+    let xdg_session_dirs = xdg_data_dirs().into_iter().flat_map(|dir| {
+        SESSION_SUBDIRS
+            .iter()
+            .map(move |(subdir, r#type)| SessionDir { r#type: *r#type, path: dir.join(subdir) })
     });
 
+    extra_dirs.iter().cloned().chain(xdg_session_dirs).collect()
+}
+
+/// Goes through the sessions defined in the config and then every session
+/// file in the session directories, in order of precedence, and works out
+/// which sessions to offer. Icons are looked up in `icon_theme`.
+pub fn discover_sessions(
+    custom_sessions: &[Session],
+    extra_dirs: &[SessionDir],
+    icon_theme: Option<&str>,
+) -> Vec<Candidate> {
+    let session_dirs = session_dirs(extra_dirs).into_iter().map(|dir| (dir.path, dir.r#type));
+
     let desktop_files = session_dirs.flat_map(|(dir, r#type)| match std::fs::read_dir(dir) {
-        Ok(entries) => entries.filter_map(Result::ok).map(|entry| (entry.path(), r#type)).collect(),
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "desktop"))
+            .sorted()
+            .map(|path| (path, r#type))
+            .collect(),
         Err(_) => Vec::new(),
     });
 
-    desktop_files
-        .map(|(path, r#type)| read_desktop_file(path, r#type))
-        .filter_map(Result::ok)
-        .unique_by(|session| session.slug.clone())
+    let locale = Locale::from_env();
+    let custom_sessions =
+        custom_sessions.iter().map(|session| (Source::Config, session.r#type, Ok(session.clone())));
+    let session_files = desktop_files.map(|(path, r#type)| {
+        let session = read_desktop_file(path.clone(), r#type, locale.as_ref(), icon_theme);
+        (Source::File(path), r#type, session)
+    });
+
+    let mut seen = HashMap::<String, Source>::new();
+
+    custom_sessions
+        .chain(session_files)
+        .map(|(source, r#type, session)| {
+            let status = match session {
+                Err(error) => Status::Invalid(error),
+                Ok(session) => match seen.entry(session.slug.clone()) {
+                    Entry::Occupied(entry) => Status::Shadowed { session, by: entry.get().clone() },
+                    Entry::Vacant(entry) => {
+                        entry.insert(source.clone());
+                        match session.check_available() {
+                            Ok(()) => Status::Offered(session),
+                            Err(reason) => Status::Unavailable { session, reason },
+                        }
+                    }
+                },
+            };
+            Candidate { source, r#type, status }
+        })
         .collect()
 }
 
-pub fn read_desktop_file(path: PathBuf, r#type: SessionType) -> Result<Session> {
-    let ini = Ini::load_from_file(&path)?;
+pub fn get_sessions(
+    custom_sessions: &[Session],
+    extra_dirs: &[SessionDir],
+    icon_theme: Option<&str>,
+) -> Vec<Session> {
+    discover_sessions(custom_sessions, extra_dirs, icon_theme)
+        .into_iter()
+        .filter_map(|candidate| match candidate.status {
+            Status::Offered(session) => Some(session),
+            Status::Invalid(error) => {
+                eprintln!("Ignoring session file {}: {:#}", candidate.source, error);
+                None
+            }
+            Status::Shadowed { .. } | Status::Unavailable { .. } => None,
+        })
+        .collect()
+}
+
+/// Prints the sessions that would be offered, or with `verbose`, every session
+/// found and what became of it.
+pub fn print_sessions(
+    custom_sessions: &[Session],
+    extra_dirs: &[SessionDir],
+    icon_theme: Option<&str>,
+    verbose: bool,
+) {
+    for candidate in discover_sessions(custom_sessions, extra_dirs, icon_theme) {
+        let session = match &candidate.status {
+            Status::Offered(session)
+            | Status::Shadowed { session, .. }
+            | Status::Unavailable { session, .. } => Some(session),
+            Status::Invalid(_) => None,
+        };
 
-    let section = ini
-        .section(Some("Desktop Entry"))
-        .ok_or_eyre("missing [Desktop Entry] section in .desktop file")?;
+        if !verbose {
+            if let Status::Offered(session) = &candidate.status {
+                println!("{}\t{}\t{}", session.slug, session.r#type, session.name);
+            }
+            continue;
+        }
+
+        println!("{}", candidate.source);
+        println!("  type: {}", candidate.r#type);
+        if let Some(session) = session {
+            println!("  session: {} ({:?})", session.slug, session.name);
+            if let Some(icon) = &session.icon {
+                println!("  icon: {}", icon.display());
+            }
+        }
+        match &candidate.status {
+            Status::Offered(_) => println!("  status: offered"),
+            Status::Shadowed { by, .. } => println!("  status: shadowed by {}", by),
+            Status::Unavailable { reason, .. } => println!("  status: unavailable: {:#}", reason),
+            Status::Invalid(error) => println!("  status: invalid: {:#}", error),
+        }
+    }
+}
+
+/// Where other display managers keep what users picked in them, so that the
+/// same can be picked here.
+#[derive(Debug, Clone)]
+pub struct OtherDisplayManagers {
+    /// AccountsService's per-user files, used by GDM and others.
+    pub accounts_service_dir: PathBuf,
+    /// The directory home directories are in, for `~/.dmrc` (LightDM and older
+    /// ones), or `None` to look each one up in the user database.
+    pub home_dirs: Option<PathBuf>,
+}
+
+impl OtherDisplayManagers {
+    /// Where they are on this machine.
+    pub fn system() -> Self {
+        Self { accounts_service_dir: PathBuf::from(ACCOUNTS_SERVICE_USERS_DIR), home_dirs: None }
+    }
+
+    fn home_dir(&self, username: &str) -> Option<PathBuf> {
+        match &self.home_dirs {
+            Some(home_dirs) => Some(home_dirs.join(username)),
+            None => Some(User::from_name(username).ok()??.dir),
+        }
+    }
+
+    /// A setting `username` picked, as recorded by AccountsService under one
+    /// of `accounts_service_keys`, or in `~/.dmrc` under `dmrc_key`.
+    fn setting(
+        &self,
+        username: &str,
+        accounts_service_keys: &[&str],
+        dmrc_key: &str,
+    ) -> Option<String> {
+        // The username is whatever was typed in, so don't let it escape the directory
+        if username.is_empty() || username.contains('/') || username.starts_with('.') {
+            return None;
+        }
+
+        let read_key = |path: &Path, group: &str, keys: &[&str]| {
+            let entry = DesktopEntry::load(path).ok()?;
+            let group = entry.group(group)?;
+            keys.iter()
+                .filter_map(|key| group.string(key))
+                .find(|value| !value.is_empty() && value != "default")
+        };
+
+        let accounts_service = || {
+            let path = self.accounts_service_dir.join(username);
+            read_key(&path, "User", accounts_service_keys)
+        };
+        let dmrc = || {
+            let home_dir = self.home_dir(username)?;
+            read_key(&home_dir.join(".dmrc"), "Desktop", &[dmrc_key])
+        };
+
+        accounts_service().or_else(dmrc)
+    }
+
+    /// The slug of the session `username` last picked.
+    pub fn preferred_session(&self, username: &str) -> Option<String> {
+        let slug = self.setting(username, &["Session", "XSession"], "Session")?;
+        Some(slug.strip_suffix(".desktop").map(str::to_owned).unwrap_or(slug))
+    }
+
+    /// The language `username` picked, e.g. `de_DE.UTF-8`.
+    pub fn language(&self, username: &str) -> Option<String> {
+        self.setting(username, &["Language"], "Language")
+    }
+}
+
+/// Parses `X-OCF-Environment=`, a list of `KEY=VALUE` pairs.
+fn parse_environment(entries: Vec<String>) -> Result<Vec<(String, String)>> {
+    entries
+        .into_iter()
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+            _ => bail!("invalid X-OCF-Environment= entry {:?}, expected KEY=VALUE", entry),
+        })
+        .collect()
+}
 
-    let name = section.get("Name").ok_or_eyre("missing Name= property in .desktop file")?;
-    let exec = section.get("Exec").ok_or_eyre("missing Exec= property in .desktop file")?;
-    let desktop_names = section.get("DesktopNames").unwrap_or("");
+pub fn read_desktop_file(
+    path: PathBuf,
+    r#type: SessionType,
+    locale: Option<&Locale>,
+    icon_theme: Option<&str>,
+) -> Result<Session> {
+    let entry = DesktopEntry::load(&path)?;
+
+    let group = entry
+        .group("Desktop Entry")
+        .ok_or_eyre("missing [Desktop Entry] group in .desktop file")?;
+
+    let name =
+        group.localized_string("Name", locale).ok_or_eyre("missing Name= key in .desktop file")?;
+    let exec = group.exec()?.ok_or_eyre("missing Exec= key in .desktop file")?;
+    let hidden =
+        group.boolean("Hidden")?.unwrap_or(false) || group.boolean("NoDisplay")?.unwrap_or(false);
 
     Ok(Session {
         slug: path.file_stem().unwrap().to_string_lossy().to_string(),
-        name: name.to_owned(),
-        exec: shlex::split(exec).ok_or_eyre("failed to parse Exec= in .desktop file")?,
+        name,
+        comment: group.localized_string("Comment", locale),
+        exec,
         r#type: r#type.to_owned(),
-        desktop_names: desktop_names.split(";").map(str::to_owned).collect(),
+        desktop_names: group.strings("DesktopNames").unwrap_or_default(),
+        icon: group.localized_string("Icon", locale).and_then(|icon| find_icon(&icon, icon_theme)),
+        env: parse_environment(group.strings("X-OCF-Environment").unwrap_or_default())?,
+        hidden,
+        try_exec: group.string("TryExec"),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(env: &[(&str, &str)]) -> Session {
+        Session {
+            slug: "sway".to_owned(),
+            name: "Sway".to_owned(),
+            comment: None,
+            exec: vec!["sway".to_owned()],
+            r#type: SessionType::Wayland,
+            desktop_names: vec!["sway".to_owned(), "wlroots".to_owned()],
+            icon: None,
+            env: env.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect(),
+            hidden: false,
+            try_exec: None,
+        }
+    }
+
+    fn environment(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn describes_session_in_environment() {
+        let env = session(&[]).to_environment(
+            None,
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            &UserSettings::default(),
+        );
+
+        assert_eq!(
+            env,
+            [
+                "XDG_CURRENT_DESKTOP=sway:wlroots",
+                "XDG_SESSION_CLASS=user",
+                "XDG_SESSION_DESKTOP=sway",
+                "XDG_SESSION_TYPE=wayland",
+            ]
+        );
+    }
+
+    #[test]
+    fn applies_environment_in_order_of_precedence() {
+        let inherited = environment(&[("LANG", "C.UTF-8"), ("XKB_DEFAULT_LAYOUT", "fr")]);
+        let site = environment(&[
+            ("EDITOR", "nano"),
+            ("LANG", "en_US.UTF-8"),
+            ("MOZ_ENABLE_WAYLAND", "0"),
+            ("XDG_SESSION_TYPE", "x11"),
+            ("XKB_DEFAULT_LAYOUT", "us"),
+        ]);
+        let session = session(&[
+            ("MOZ_ENABLE_WAYLAND", "1"),
+            ("XDG_SESSION_DESKTOP", "other"),
+            ("XKB_DEFAULT_LAYOUT", "de"),
+        ]);
+        let user = UserSettings { lang: Some("de_DE.UTF-8".to_owned()) };
+        let display =
+            XDisplay { number: 1, xauthority: Some(PathBuf::from("/home/a/.Xauthority")) };
+
+        let env = session.to_environment(Some(&display), &inherited, &site, &user);
+
+        assert_eq!(
+            env,
+            [
+                "DISPLAY=:1",
+                "EDITOR=nano",
+                "LANG=de_DE.UTF-8",
+                "MOZ_ENABLE_WAYLAND=1",
+                "XAUTHORITY=/home/a/.Xauthority",
+                "XDG_CURRENT_DESKTOP=sway:wlroots",
+                "XDG_SESSION_CLASS=user",
+                "XDG_SESSION_DESKTOP=sway",
+                "XDG_SESSION_TYPE=wayland",
+                "XKB_DEFAULT_LAYOUT=de",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_site_language_without_user_language() {
+        let fixture = DisplayManagerFixture::new();
+        let inherited = environment(&[("LANG", "C.UTF-8"), ("XKB_DEFAULT_LAYOUT", "de")]);
+        let site = environment(&[("LANG", "en_US.UTF-8")]);
+        let user = UserSettings::for_user(&fixture.display_managers, "alice");
+
+        let env = session(&[]).to_environment(None, &inherited, &site, &user);
+
+        assert!(env.contains(&"LANG=en_US.UTF-8".to_owned()));
+        assert!(env.contains(&"XKB_DEFAULT_LAYOUT=de".to_owned()));
+    }
+
+    #[test]
+    fn applies_user_language_over_site_one() {
+        let fixture = DisplayManagerFixture::new();
+        fixture.write_accounts_service("alice", "Language=de_DE.UTF-8\n");
+        let site = environment(&[("LANG", "en_US.UTF-8")]);
+        let user = UserSettings::for_user(&fixture.display_managers, "alice");
+
+        let env = session(&[]).to_environment(None, &BTreeMap::new(), &site, &user);
+
+        assert!(env.contains(&"LANG=de_DE.UTF-8".to_owned()));
+    }
+
+    #[test]
+    fn reads_environment_from_desktop_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sway.desktop");
+
+        std::fs::write(
+            &path,
+            "[Desktop Entry]\nName=Sway\nExec=sway\nX-OCF-Environment=WLR_RENDERER=vulkan;GREETING=a=b\\;c;\n",
+        )
+        .unwrap();
+        let session = read_desktop_file(path.clone(), SessionType::Wayland, None, None).unwrap();
+        assert_eq!(
+            session.env,
+            [
+                ("WLR_RENDERER".to_owned(), "vulkan".to_owned()),
+                ("GREETING".to_owned(), "a=b;c".to_owned()),
+            ]
+        );
+
+        std::fs::write(&path, "[Desktop Entry]\nName=Sway\nExec=sway\nX-OCF-Environment=oops;\n")
+            .unwrap();
+        assert!(read_desktop_file(path, SessionType::Wayland, None, None).is_err());
+    }
+
+    #[test]
+    fn prefers_wayland_sessions_to_x11_ones() {
+        let dirs = session_dirs(&[]);
+
+        assert_eq!(dirs[0].r#type, SessionType::Wayland);
+        assert!(dirs[0].path.ends_with("wayland-sessions"));
+        assert_eq!(dirs[1].r#type, SessionType::X11);
+        assert_eq!(dirs[1].path.parent(), dirs[0].path.parent());
+    }
+
+    fn x_launcher() -> Argv {
+        "startx /usr/bin/env {exec} -- :{display} vt{display}".parse().unwrap()
+    }
+
+    #[test]
+    fn wraps_x11_session_in_x_launcher() {
+        let session = Session {
+            exec: vec!["i3".to_owned(), "--shmlog-size".to_owned(), "0".to_owned()],
+            r#type: SessionType::X11,
+            ..session(&[])
+        };
+        let display = XDisplay { number: 2, xauthority: None };
+
+        assert_eq!(
+            session.to_command(Some(&display), &x_launcher()),
+            ["startx", "/usr/bin/env", "i3", "--shmlog-size", "0", "--", ":2", "vt2"]
+        );
+    }
+
+    #[test]
+    fn starts_session_without_display_as_is() {
+        assert_eq!(session(&[]).to_command(None, &x_launcher()), ["sway"]);
+    }
+
+    #[test]
+    fn sets_display_without_xauthority() {
+        let session = Session { r#type: SessionType::X11, ..session(&[]) };
+        let display = XDisplay { number: 2, xauthority: None };
+
+        let env = session.to_environment(
+            Some(&display),
+            &BTreeMap::new(),
+            &BTreeMap::new(),
+            &UserSettings::default(),
+        );
+
+        assert!(env.contains(&"DISPLAY=:2".to_owned()));
+        assert!(!env.iter().any(|entry| entry.starts_with("XAUTHORITY=")));
+    }
+
+    #[test]
+    fn overrides_site_display() {
+        let site = environment(&[("DISPLAY", ":0"), ("XAUTHORITY", "/tmp/xauth")]);
+        let display =
+            XDisplay { number: 3, xauthority: Some(PathBuf::from("/home/a/.Xauthority")) };
+
+        let env = session(&[]).to_environment(
+            Some(&display),
+            &BTreeMap::new(),
+            &site,
+            &UserSettings::default(),
+        );
+
+        assert!(env.contains(&"DISPLAY=:3".to_owned()));
+        assert!(env.contains(&"XAUTHORITY=/home/a/.Xauthority".to_owned()));
+        assert!(!env.contains(&"DISPLAY=:0".to_owned()));
+    }
+
+    /// A program every test machine has, for sessions that should be offered.
+    static SHELL: &str = "/bin/sh";
+
+    fn write_session(dir: &Path, slug: &str, contents: &str) -> PathBuf {
+        let path = dir.join(format!("{}.desktop", slug));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Discovers sessions with `dirs` as the extra session directories, leaving
+    /// out whatever is installed in the XDG data directories.
+    fn discover(custom_sessions: &[Session], dirs: &[&Path]) -> Vec<Candidate> {
+        let extra_dirs: Vec<_> = dirs
+            .iter()
+            .map(|dir| SessionDir { r#type: SessionType::Wayland, path: dir.to_path_buf() })
+            .collect();
+
+        discover_sessions(custom_sessions, &extra_dirs, None)
+            .into_iter()
+            .filter(|candidate| match &candidate.source {
+                Source::Config => true,
+                Source::File(path) => dirs.iter().any(|dir| path.starts_with(dir)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn earlier_session_dirs_take_precedence() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let contents = format!("[Desktop Entry]\nName=Test\nExec={}\n", SHELL);
+        let first_path = write_session(first.path(), "ocf-test", &contents);
+        let second_path = write_session(second.path(), "ocf-test", &contents);
+
+        let candidates = discover(&[], &[first.path(), second.path()]);
+
+        assert_eq!(candidates.len(), 2);
+        assert!(matches!(&candidates[0].source, Source::File(path) if *path == first_path));
+        assert!(
+            matches!(&candidates[0].status, Status::Offered(session) if session.slug == "ocf-test")
+        );
+        assert!(matches!(&candidates[1].source, Source::File(path) if *path == second_path));
+        assert!(matches!(
+            &candidates[1].status,
+            Status::Shadowed { by: Source::File(path), .. } if *path == first_path
+        ));
+    }
+
+    #[test]
+    fn config_sessions_take_precedence_over_session_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = format!("[Desktop Entry]\nName=From file\nExec={}\n", SHELL);
+        write_session(dir.path(), "ocf-test", &contents);
+        let custom = Session {
+            slug: "ocf-test".to_owned(),
+            name: "From config".to_owned(),
+            exec: vec![SHELL.to_owned()],
+            ..session(&[])
+        };
+
+        let candidates = discover(&[custom], &[dir.path()]);
+
+        assert_eq!(candidates.len(), 2);
+        assert!(matches!(candidates[0].source, Source::Config));
+        assert!(
+            matches!(&candidates[0].status, Status::Offered(session) if session.name == "From config")
+        );
+        assert!(matches!(
+            &candidates[1].status,
+            Status::Shadowed { session, by: Source::Config } if session.name == "From file"
+        ));
+    }
+
+    /// The reason `contents` isn't offered as a session, if it isn't.
+    fn unavailable_reason(contents: &str) -> Option<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_session(dir.path(), "ocf-test", contents);
+        let session = read_desktop_file(path, SessionType::Wayland, None, None).unwrap();
+        session.check_available().err().map(|error| error.to_string())
+    }
+
+    #[test]
+    fn offers_only_available_sessions() {
+        let entry = format!("[Desktop Entry]\nName=Test\nExec={} -l\n", SHELL);
+        let missing = "/nonexistent/ocf-greeter-test";
+
+        assert_eq!(unavailable_reason(&entry), None);
+        assert_eq!(unavailable_reason(&format!("{}TryExec={}\n", entry, SHELL)), None);
+        assert_eq!(
+            unavailable_reason(&format!("{}Hidden=true\n", entry)).as_deref(),
+            Some("session is marked Hidden or NoDisplay")
+        );
+        assert_eq!(
+            unavailable_reason(&format!("{}NoDisplay=true\n", entry)).as_deref(),
+            Some("session is marked Hidden or NoDisplay")
+        );
+        assert_eq!(
+            unavailable_reason(&format!("{}TryExec={}\n", entry, missing)),
+            Some(format!("TryExec= program {} not found", missing))
+        );
+        assert_eq!(
+            unavailable_reason(&format!(
+                "[Desktop Entry]\nName=Test\nExec={} {}\n",
+                missing, SHELL
+            )),
+            Some(format!("Exec= program {} not found", missing))
+        );
+    }
+
+    #[test]
+    fn hidden_sessions_still_shadow_later_ones() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        let contents = format!("[Desktop Entry]\nName=Test\nExec={}\n", SHELL);
+        let first_path =
+            write_session(first.path(), "ocf-test", &format!("{}Hidden=true\n", contents));
+        write_session(second.path(), "ocf-test", &contents);
+
+        let candidates = discover(&[], &[first.path(), second.path()]);
+
+        assert!(matches!(candidates[0].status, Status::Unavailable { .. }));
+        assert!(matches!(
+            &candidates[1].status,
+            Status::Shadowed { by: Source::File(path), .. } if *path == first_path
+        ));
+    }
+
+    #[test]
+    fn reports_invalid_session_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_session(dir.path(), "ocf-test", "[Desktop Entry]\nName=Test\n");
+
+        let candidates = discover(&[], &[dir.path()]);
+
+        assert_eq!(candidates.len(), 1);
+        assert!(matches!(&candidates[0].source, Source::File(source) if *source == path));
+        assert!(matches!(
+            &candidates[0].status,
+            Status::Invalid(error) if error.to_string() == "missing Exec= key in .desktop file"
+        ));
+    }
+
+    struct DisplayManagerFixture {
+        dir: tempfile::TempDir,
+        display_managers: OtherDisplayManagers,
+    }
+
+    impl DisplayManagerFixture {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let display_managers = OtherDisplayManagers {
+                accounts_service_dir: dir.path().join("accounts"),
+                home_dirs: Some(dir.path().join("home")),
+            };
+            std::fs::create_dir(&display_managers.accounts_service_dir).unwrap();
+            std::fs::create_dir_all(dir.path().join("home/alice")).unwrap();
+            Self { dir, display_managers }
+        }
+
+        fn write_accounts_service(&self, username: &str, contents: &str) {
+            let path = self.display_managers.accounts_service_dir.join(username);
+            std::fs::write(path, format!("[User]\n{}", contents)).unwrap();
+        }
+
+        fn write_dmrc(&self, contents: &str) {
+            let path = self.dir.path().join("home/alice/.dmrc");
+            std::fs::write(path, format!("[Desktop]\n{}", contents)).unwrap();
+        }
+
+        fn preferred_session(&self, username: &str) -> Option<String> {
+            self.display_managers.preferred_session(username)
+        }
+    }
+
+    #[test]
+    fn reads_preferred_session_from_accounts_service() {
+        let fixture = DisplayManagerFixture::new();
+        assert_eq!(fixture.preferred_session("alice"), None);
+
+        fixture.write_accounts_service("alice", "XSession=i3\n");
+        assert_eq!(fixture.preferred_session("alice").as_deref(), Some("i3"));
+
+        fixture.write_accounts_service("alice", "XSession=i3\nSession=sway.desktop\n");
+        assert_eq!(fixture.preferred_session("alice").as_deref(), Some("sway"));
+
+        fixture.write_accounts_service("alice", "Session=default\nXSession=\n");
+        assert_eq!(fixture.preferred_session("alice"), None);
+    }
+
+    #[test]
+    fn falls_back_to_dmrc() {
+        let fixture = DisplayManagerFixture::new();
+        fixture.write_dmrc("Session=plasma\nLanguage=de_DE.UTF-8\n");
+        assert_eq!(fixture.preferred_session("alice").as_deref(), Some("plasma"));
+        assert_eq!(fixture.display_managers.language("alice").as_deref(), Some("de_DE.UTF-8"));
+
+        // AccountsService comes first, unless it only has the default
+        fixture.write_accounts_service("alice", "Session=default\n");
+        assert_eq!(fixture.preferred_session("alice").as_deref(), Some("plasma"));
+        fixture.write_accounts_service("alice", "Session=gnome\n");
+        assert_eq!(fixture.preferred_session("alice").as_deref(), Some("gnome"));
+    }
+
+    #[test]
+    fn rejects_usernames_that_are_paths() {
+        let fixture = DisplayManagerFixture::new();
+        std::fs::write(fixture.dir.path().join("escaped"), "[User]\nSession=escaped\n").unwrap();
+        fixture.write_accounts_service(".hidden", "Session=hidden\n");
+
+        assert_eq!(fixture.preferred_session("../escaped"), None);
+        assert_eq!(fixture.preferred_session(".hidden"), None);
+        assert_eq!(fixture.preferred_session(""), None);
+    }
+}
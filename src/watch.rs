@@ -0,0 +1,253 @@
+//! Watches the config file and session directories with inotify, so that
+//! changes to them show up without restarting the greeter.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+
+use crate::args::SessionDir;
+use crate::config::{self, get_config, Config};
+use crate::sessions;
+
+/// How long to wait after a change for any that come with it, e.g. from a
+/// package manager installing several session files at once.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// The names of the entries in a watched directory that matter, or `None` if
+/// all of them do.
+type Filter = Option<HashSet<OsString>>;
+
+struct Watcher {
+    inotify: Inotify,
+    filters: HashMap<WatchDescriptor, Filter>,
+}
+
+impl Watcher {
+    /// Starts watching the config file and the session directories.
+    fn new(config_path: &Path, session_dirs: &[SessionDir]) -> Result<Self> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC).wrap_err("failed to start inotify")?;
+        let mut watcher = Self { inotify, filters: HashMap::new() };
+
+        // Editors and package managers usually replace files rather than
+        // writing to them, so watch the directory the config file is in
+        watcher.watch_entry(config_path);
+
+        for dir in session_dirs {
+            if dir.path.is_dir() {
+                watcher.watch(&dir.path, None);
+            } else {
+                // Most of these don't exist until something is installed in them
+                watcher.watch_entry(&dir.path);
+            }
+        }
+
+        Ok(watcher)
+    }
+
+    /// Watches the directory `path` is in for changes to `path`. If that
+    /// directory doesn't exist either, the nearest one up that does is watched
+    /// instead, for whatever leads to `path` being created in it.
+    fn watch_entry(&mut self, mut path: &Path) {
+        while let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            let dir = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            if dir.is_dir() {
+                self.watch(dir, Some(name.to_owned()));
+                return;
+            }
+            path = parent;
+        }
+    }
+
+    fn watch(&mut self, dir: &Path, name: Option<OsString>) {
+        let flags = AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_DELETE_SELF
+            | AddWatchFlags::IN_MOVE_SELF
+            | AddWatchFlags::IN_ONLYDIR;
+
+        // A directory that has gone away in the meantime will be noticed by the
+        // watch on its parent
+        let Ok(wd) = self.inotify.add_watch(dir, flags) else {
+            return;
+        };
+
+        // Watching the same directory twice gives the same descriptor back
+        match (self.filters.entry(wd), name) {
+            (Entry::Vacant(entry), name) => {
+                entry.insert(name.map(|name| HashSet::from([name])));
+            }
+            (Entry::Occupied(mut entry), Some(name)) => {
+                if let Some(names) = entry.get_mut() {
+                    names.insert(name);
+                }
+            }
+            (Entry::Occupied(mut entry), None) => {
+                entry.insert(None);
+            }
+        }
+    }
+
+    /// Blocks until something that matters changes.
+    fn wait(&self) -> Result<()> {
+        loop {
+            let events = self.inotify.read_events().wrap_err("failed to read inotify events")?;
+            let relevant = events.iter().any(|event| match self.filters.get(&event.wd) {
+                Some(Some(names)) => event.name.as_ref().is_some_and(|name| names.contains(name)),
+                // Events about the directory itself have no name
+                Some(None) => true,
+                None => false,
+            });
+            if relevant {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Waits for a change to what `watch` watches, then calls `watch` again, as
+/// what to watch may have changed too, followed by `reload` and `on_change`.
+/// Watching again before either of them looks at anything means that nothing
+/// is missed in between. Runs until `on_change` returns false.
+fn watch_until(
+    mut watch: impl FnMut() -> Result<Watcher>,
+    mut reload: impl FnMut(),
+    mut on_change: impl FnMut() -> bool,
+) -> Result<()> {
+    let mut watcher = watch()?;
+
+    loop {
+        watcher.wait()?;
+        std::thread::sleep(SETTLE_TIME);
+
+        watcher = watch()?;
+        reload();
+
+        if !on_change() {
+            return Ok(());
+        }
+    }
+}
+
+/// Reloads the config whenever it or the session directories change, and
+/// then calls `on_change`. Runs until `on_change` returns false, so should be
+/// run on a thread of its own.
+pub fn run(on_change: impl FnMut() -> bool) -> Result<()> {
+    let watch = || {
+        let session_dirs = sessions::session_dirs(&get_config().session_dirs);
+        Watcher::new(Config::search_path(), &session_dirs)
+    };
+    let reload = || {
+        if let Err(error) = config::reload() {
+            eprintln!("Failed to reload config, keeping the old one: {:#}", error);
+        }
+    };
+
+    watch_until(watch, reload, on_change)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::sessions::SessionType;
+
+    /// How long to give a change to be noticed before failing.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Where the config file goes, relative to the fixture's directory. Its
+    /// directory doesn't exist to begin with.
+    const CONFIG_PATH: &str = "etc/ocf-greeter/config.toml";
+
+    struct Fixture {
+        dir: tempfile::TempDir,
+        /// Sent to every time what's watched has been set up again.
+        ready: mpsc::Receiver<()>,
+        changes: mpsc::Receiver<()>,
+    }
+
+    impl Fixture {
+        fn config_path(&self) -> PathBuf {
+            self.dir.path().join(CONFIG_PATH)
+        }
+
+        fn sessions_dir(&self) -> PathBuf {
+            self.dir.path().join("sessions")
+        }
+    }
+
+    /// Watches a config file and a session directory in a new temporary
+    /// directory, on a thread of its own, reporting every change.
+    fn start_watching() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join(CONFIG_PATH);
+        let session_dirs =
+            [SessionDir { r#type: SessionType::Wayland, path: dir.path().join("sessions") }];
+        std::fs::create_dir(&session_dirs[0].path).unwrap();
+
+        let (ready_sender, ready) = mpsc::channel();
+        let (change_sender, changes) = mpsc::channel();
+        std::thread::spawn(move || {
+            let watch = || {
+                let watcher = Watcher::new(&config_path, &session_dirs);
+                _ = ready_sender.send(());
+                watcher
+            };
+            watch_until(watch, || {}, || change_sender.send(()).is_ok())
+        });
+
+        ready.recv_timeout(TIMEOUT).unwrap();
+        Fixture { dir, ready, changes }
+    }
+
+    #[test]
+    fn notices_new_session_file() {
+        let fixture = start_watching();
+
+        std::fs::write(fixture.sessions_dir().join("sway.desktop"), "").unwrap();
+
+        fixture.changes.recv_timeout(TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn notices_config_file_in_new_directory() {
+        let fixture = start_watching();
+        let config_path = fixture.config_path();
+
+        std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+        fixture.changes.recv_timeout(TIMEOUT).unwrap();
+        fixture.ready.recv_timeout(TIMEOUT).unwrap();
+
+        std::fs::write(&config_path, "").unwrap();
+        fixture.changes.recv_timeout(TIMEOUT).unwrap();
+    }
+
+    #[test]
+    fn notices_replaced_config_file_only() {
+        let fixture = start_watching();
+        let config_path = fixture.config_path();
+        let config_dir = config_path.parent().unwrap();
+        std::fs::create_dir_all(config_dir).unwrap();
+        fixture.changes.recv_timeout(TIMEOUT).unwrap();
+        fixture.ready.recv_timeout(TIMEOUT).unwrap();
+
+        std::fs::write(config_dir.join("unrelated"), "").unwrap();
+        assert!(fixture.changes.recv_timeout(SETTLE_TIME * 2).is_err());
+
+        let new_config = config_dir.join("config.toml.new");
+        std::fs::write(&new_config, "").unwrap();
+        std::fs::rename(&new_config, &config_path).unwrap();
+
+        fixture.changes.recv_timeout(TIMEOUT).unwrap();
+    }
+}
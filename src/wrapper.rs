@@ -0,0 +1,153 @@
+//! Commands that sessions are started inside of, e.g. to load the user's
+//! shell profile first.
+
+use std::path::{Path, PathBuf};
+
+use nix::unistd::User;
+use serde::Deserialize;
+
+/// A command put in front of a session's command line. Either the name of a
+/// preset, or a command line of its own, in which `{slug}` is replaced by the
+/// session's slug.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a preset (\"none\", \"login-shell\", \"uwsm\" or \"systemd-cat\") or a command as a list of strings"
+)]
+pub enum Wrapper {
+    Preset(Preset),
+    Command(Vec<String>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Start the session as is.
+    None,
+    /// Run the session from the user's login shell, so that their profile is
+    /// loaded first.
+    LoginShell,
+    /// Start the session as systemd units with uwsm.
+    Uwsm,
+    /// Send the session's output to the journal, tagged with its slug.
+    SystemdCat,
+}
+
+impl Default for Wrapper {
+    fn default() -> Self {
+        Self::Preset(Preset::None)
+    }
+}
+
+/// Quotes `arg` for any POSIX-ish shell, including fish.
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// The shell `username` logs in with, or `/bin/sh` if they don't have one.
+pub fn login_shell(username: &str) -> PathBuf {
+    match User::from_name(username) {
+        Ok(Some(user)) if !user.shell.as_os_str().is_empty() => user.shell,
+        _ => PathBuf::from("/bin/sh"),
+    }
+}
+
+impl Wrapper {
+    /// Wraps the command line `cmd` of the session `slug`, for a user whose
+    /// login shell is `shell`, see [`login_shell`].
+    pub fn wrap(&self, cmd: Vec<String>, slug: &str, shell: &Path) -> Vec<String> {
+        let prefix = match self {
+            Self::Preset(Preset::None) => return cmd,
+            Self::Preset(Preset::LoginShell) => {
+                let script = cmd.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+                return vec![
+                    shell.to_string_lossy().into_owned(),
+                    "-l".to_owned(),
+                    "-c".to_owned(),
+                    format!("exec {}", script),
+                ];
+            }
+            Self::Preset(Preset::Uwsm) => {
+                vec!["uwsm".to_owned(), "start".to_owned(), "--".to_owned()]
+            }
+            Self::Preset(Preset::SystemdCat) => {
+                vec!["systemd-cat".to_owned(), format!("--identifier={}", slug)]
+            }
+            Self::Command(prefix) => prefix.iter().map(|arg| arg.replace("{slug}", slug)).collect(),
+        };
+
+        prefix.into_iter().chain(cmd).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static SHELL: &str = "/usr/bin/fish";
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn quotes_for_shell() {
+        assert_eq!(shell_quote("sway"), "'sway'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+    }
+
+    #[test]
+    fn shell_parses_quoted_arguments_back() {
+        let args = argv(&["it's", "two  words", "$HOME", "`id`", "a\\b", ""]);
+        let script = args.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+
+        let output = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\n' {}", script))
+            .output()
+            .unwrap();
+
+        let parsed: Vec<_> =
+            String::from_utf8(output.stdout).unwrap().lines().map(str::to_owned).collect();
+        assert_eq!(parsed, args);
+    }
+
+    #[test]
+    fn runs_session_from_login_shell() {
+        let wrapped = Wrapper::Preset(Preset::LoginShell).wrap(
+            argv(&["sway", "--config", "/home/a/it's mine/$config"]),
+            "sway",
+            Path::new(SHELL),
+        );
+
+        assert_eq!(
+            wrapped,
+            argv(&[SHELL, "-l", "-c", r"exec 'sway' '--config' '/home/a/it'\''s mine/$config'"])
+        );
+    }
+
+    #[test]
+    fn wraps_session_in_presets() {
+        let cmd = argv(&["sway", "--unsupported-gpu"]);
+        let wrap = |preset| Wrapper::Preset(preset).wrap(cmd.clone(), "sway", Path::new(SHELL));
+
+        assert_eq!(wrap(Preset::None), cmd);
+        assert_eq!(wrap(Preset::Uwsm), argv(&["uwsm", "start", "--", "sway", "--unsupported-gpu"]));
+        assert_eq!(
+            wrap(Preset::SystemdCat),
+            argv(&["systemd-cat", "--identifier=sway", "sway", "--unsupported-gpu"])
+        );
+    }
+
+    #[test]
+    fn wraps_session_in_command() {
+        let wrapper = Wrapper::Command(argv(&["run-session", "--name={slug}", "--"]));
+
+        assert_eq!(
+            wrapper.wrap(argv(&["sway"]), "sway", Path::new(SHELL)),
+            argv(&["run-session", "--name=sway", "--", "sway"])
+        );
+    }
+}